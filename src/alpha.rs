@@ -0,0 +1,73 @@
+use image::Rgba;
+use std::str::FromStr;
+
+use crate::parse_color;
+
+/// What becomes of the transparent parts of the image, in every output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alpha {
+    /// Pixels less opaque than the threshold are left blank; the others are
+    /// drawn opaque.
+    Threshold(u8),
+    /// Pixels are composited over an opaque matte color before conversion.
+    Matte(Rgba<u8>),
+}
+
+impl Alpha {
+    /// `Threshold` at half opacity.
+    pub const THRESHOLD: Alpha = Alpha::Threshold(128);
+
+    /// Whether a pixel of opacity `alpha` is left blank.
+    pub fn is_blank(self, alpha: u8) -> bool {
+        matches!(self, Alpha::Threshold(threshold) if alpha < threshold)
+    }
+
+    /// The opaque color `pixel` is drawn in.
+    pub fn composite(self, pixel: Rgba<u8>) -> Rgba<u8> {
+        let [r, g, b, a] = pixel.0;
+        match self {
+            Alpha::Threshold(_) => Rgba([r, g, b, 255]),
+            Alpha::Matte(matte) => {
+                let opacity = a as f32 / 255.0;
+                let blend = |c: u8, m: u8| (c as f32 * opacity + m as f32 * (1.0 - opacity)).round() as u8;
+                Rgba([blend(r, matte[0]), blend(g, matte[1]), blend(b, matte[2]), 255])
+            },
+        }
+    }
+}
+
+impl Default for Alpha {
+    fn default() -> Self {
+        Alpha::THRESHOLD
+    }
+}
+
+impl FromStr for Alpha {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "threshold" => Ok(Alpha::THRESHOLD),
+            _ => parse_color(s)
+                .map(Alpha::Matte)
+                .map_err(|_| format!("invalid alpha policy '{}' (expected threshold or a matte color such as #ffffff)", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_composite() {
+        let pixel = Rgba([200, 100, 0, 64]);
+        assert!(Alpha::THRESHOLD.is_blank(pixel[3]));
+        assert_eq!(Alpha::THRESHOLD.composite(pixel), Rgba([200, 100, 0, 255]));
+
+        let matte: Alpha = "#fff".parse().unwrap();
+        assert!(!matte.is_blank(0));
+        assert_eq!(matte.composite(pixel), Rgba([241, 216, 191, 255]));
+        assert_eq!(matte.composite(Rgba([1, 2, 3, 0])), Rgba([255, 255, 255, 255]));
+    }
+}
@@ -0,0 +1,151 @@
+use image::Rgba;
+use std::io::IsTerminal;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::color::lab;
+
+/// How many colors the terminal can show.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorDepth {
+    /// 24-bit colors.
+    TrueColor,
+    /// The xterm 256-color palette.
+    Ansi256,
+    /// The 16 basic ANSI colors.
+    Ansi16,
+    /// No colors: plain text.
+    Mono,
+}
+
+impl ColorDepth {
+    /// Guesses the depth of standard output from `NO_COLOR`, `COLORTERM` and
+    /// `TERM`, falling back to `Mono` when it is not a terminal.
+    pub fn detect() -> ColorDepth {
+        if !std::io::stdout().is_terminal() {
+            return ColorDepth::Mono;
+        }
+        let var = |name| std::env::var(name).unwrap_or_default();
+        ColorDepth::from_env(&var("NO_COLOR"), &var("COLORTERM"), &var("TERM"))
+    }
+
+    fn from_env(no_color: &str, colorterm: &str, term: &str) -> ColorDepth {
+        if !no_color.is_empty() {
+            ColorDepth::Mono
+        } else if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Ansi256
+        } else if term.is_empty() || term == "dumb" {
+            ColorDepth::Mono
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+}
+
+impl FromStr for ColorDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "truecolor" | "24bit" => Ok(ColorDepth::TrueColor),
+            "256" => Ok(ColorDepth::Ansi256),
+            "16" => Ok(ColorDepth::Ansi16),
+            "mono" | "none" => Ok(ColorDepth::Mono),
+            _ => Err(format!("unknown color depth '{}' (expected truecolor, 256, 16 or mono)", s)),
+        }
+    }
+}
+
+// The xterm defaults for the 16 basic colors
+const ANSI16: [[u8; 3]; 16] = [
+    [0, 0, 0], [205, 0, 0], [0, 205, 0], [205, 205, 0], [0, 0, 238], [205, 0, 205], [0, 205, 205], [229, 229, 229],
+    [127, 127, 127], [255, 0, 0], [0, 255, 0], [255, 255, 0], [92, 92, 255], [255, 0, 255], [0, 255, 255], [255, 255, 255],
+];
+
+// Channel levels of the 6x6x6 color cube of the 256-color palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+// RGB of entry `index` of the xterm 256-color palette
+fn xterm_rgb(index: u8) -> [u8; 3] {
+    match index {
+        0..=15 => ANSI16[index as usize],
+        16..=231 => {
+            let i = index as usize - 16;
+            [CUBE_LEVELS[i / 36], CUBE_LEVELS[i / 6 % 6], CUBE_LEVELS[i % 6]]
+        },
+        _ => [8 + 10 * (index - 232); 3],
+    }
+}
+
+// Index in `palette` of the entry closest to `color` in CIELAB
+fn nearest(palette: &[(u8, [f32; 3])], color: Rgba<u8>) -> u8 {
+    let target = lab([color[0], color[1], color[2]]);
+    let distance = |lab: &[f32; 3]| lab.iter().zip(&target).map(|(a, b)| (a - b) * (a - b)).sum::<f32>();
+    palette
+        .iter()
+        .min_by(|a, b| distance(&a.1).total_cmp(&distance(&b.1)))
+        .map_or(0, |(index, _)| *index)
+}
+
+// The 16 basic colors, or the cube and grey ramp of the 256-color palette;
+// its first 16 entries are left out as terminals theme them freely
+fn palette(depth: ColorDepth) -> &'static [(u8, [f32; 3])] {
+    static ANSI16_LAB: OnceLock<Vec<(u8, [f32; 3])>> = OnceLock::new();
+    static ANSI256_LAB: OnceLock<Vec<(u8, [f32; 3])>> = OnceLock::new();
+    match depth {
+        ColorDepth::Ansi16 => ANSI16_LAB.get_or_init(|| (0..16).map(|i| (i, lab(xterm_rgb(i)))).collect()),
+        _ => ANSI256_LAB.get_or_init(|| (16..=255).map(|i| (i, lab(xterm_rgb(i)))).collect()),
+    }
+}
+
+// SGR parameters selecting `color` as foreground, or background when
+// `background` is set
+fn color_params(depth: ColorDepth, color: Rgba<u8>, background: bool) -> String {
+    let base = if background { 40 } else { 30 };
+    match depth {
+        ColorDepth::TrueColor => format!("{};2;{};{};{}", base + 8, color[0], color[1], color[2]),
+        ColorDepth::Ansi256 => format!("{};5;{}", base + 8, nearest(palette(depth), color)),
+        ColorDepth::Ansi16 => match nearest(palette(depth), color) {
+            index @ 0..=7 => (base + index as u32).to_string(),
+            index => (base + 60 + index as u32 - 8).to_string(),
+        },
+        ColorDepth::Mono => String::new(),
+    }
+}
+
+/// The escape sequence setting the colors of a cell, empty in `Mono`.
+pub(crate) fn sgr(depth: ColorDepth, color: Rgba<u8>, background: Option<Rgba<u8>>) -> String {
+    if depth == ColorDepth::Mono {
+        return String::new();
+    }
+    match background {
+        Some(background) => format!("\x1b[{};{}m", color_params(depth, color, false), color_params(depth, background, true)),
+        None => format!("\x1b[{}m", color_params(depth, color, false)),
+    }
+}
+
+pub(crate) const RESET: &str = "\x1b[0m";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest_palette_entries() {
+        assert_eq!(sgr(ColorDepth::TrueColor, Rgba([1, 2, 3, 255]), None), "\x1b[38;2;1;2;3m");
+        assert_eq!(sgr(ColorDepth::Ansi256, Rgba([255, 0, 0, 255]), Some(Rgba([128, 128, 128, 255]))), "\x1b[38;5;196;48;5;244m");
+        assert_eq!(sgr(ColorDepth::Ansi16, Rgba([250, 250, 250, 255]), Some(Rgba([200, 0, 0, 255]))), "\x1b[97;41m");
+        assert_eq!(sgr(ColorDepth::Mono, Rgba([250, 250, 250, 255]), None), "");
+    }
+
+    #[test]
+    fn test_detect_from_env() {
+        assert_eq!(ColorDepth::from_env("1", "truecolor", "xterm-256color"), ColorDepth::Mono);
+        assert_eq!(ColorDepth::from_env("", "truecolor", "xterm"), ColorDepth::TrueColor);
+        assert_eq!(ColorDepth::from_env("", "", "screen-256color"), ColorDepth::Ansi256);
+        assert_eq!(ColorDepth::from_env("", "", "vt100"), ColorDepth::Ansi16);
+        assert_eq!(ColorDepth::from_env("", "", "dumb"), ColorDepth::Mono);
+    }
+}
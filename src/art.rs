@@ -0,0 +1,373 @@
+use image::{DynamicImage, ImageError, ImageFormat, Rgba, RgbaImage};
+use imageproc::drawing::{draw_filled_circle_mut, draw_filled_rect_mut};
+use imageproc::rect::Rect;
+use std::fmt;
+use std::sync::Arc;
+use unicode_width::UnicodeWidthStr;
+
+use crate::ansi::{self, ColorDepth};
+use crate::fonts::FontChain;
+use crate::{html, svg, Error, GlyphAtlas, Options, OutputFormat, Result};
+
+// The text of a cell: a single character stored inline, or a grapheme
+// cluster such as a flag or ZWJ emoji sequence
+#[derive(Clone, Debug, PartialEq)]
+enum Glyph {
+    Char { utf8: [u8; 4], len: u8 },
+    Cluster(Arc<str>),
+}
+
+impl Glyph {
+    fn from_char(ch: char) -> Glyph {
+        let mut utf8 = [0; 4];
+        let len = ch.encode_utf8(&mut utf8).len() as u8;
+        Glyph::Char { utf8, len }
+    }
+
+    fn from_str(glyph: &str) -> Glyph {
+        let mut chars = glyph.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => Glyph::from_char(ch),
+            _ => Glyph::Cluster(Arc::from(glyph)),
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            Glyph::Char { utf8, len } => std::str::from_utf8(&utf8[..*len as usize]).unwrap(),
+            Glyph::Cluster(glyph) => glyph,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColoredChar {
+    glyph: Glyph,
+    color: Rgba<u8>,
+    background: Option<Rgba<u8>>,
+}
+
+impl ColoredChar {
+    pub fn new(ch: char, color: Rgba<u8>) -> ColoredChar {
+        ColoredChar { glyph: Glyph::from_char(ch), color, background: None }
+    }
+
+    /// A cell holding a whole grapheme cluster, e.g. a charset entry.
+    pub fn from_glyph(glyph: &str, color: Rgba<u8>) -> ColoredChar {
+        ColoredChar { glyph: Glyph::from_str(glyph), color, background: None }
+    }
+
+    pub fn with_background(ch: char, color: Rgba<u8>, background: Rgba<u8>) -> ColoredChar {
+        ColoredChar { glyph: Glyph::from_char(ch), color, background: Some(background) }
+    }
+
+    // A transparent space, for transparent parts of the image
+    pub(crate) fn blank() -> ColoredChar {
+        ColoredChar::new(' ', Rgba([255, 255, 255, 0]))
+    }
+
+    pub(crate) fn is_blank(&self) -> bool {
+        self.background.is_none() && self.color[3] == 0 && self.glyph() == " "
+    }
+
+    /// The first character of the glyph.
+    pub fn ch(&self) -> char {
+        self.glyph.as_str().chars().next().unwrap_or(' ')
+    }
+
+    /// The full glyph, which may span several characters.
+    pub fn glyph(&self) -> &str {
+        self.glyph.as_str()
+    }
+
+    pub fn color(&self) -> Rgba<u8> {
+        self.color
+    }
+
+    pub fn set_ch(&mut self, ch: char) {
+        self.glyph = Glyph::from_char(ch);
+    }
+
+    pub fn set_glyph(&mut self, glyph: &str) {
+        self.glyph = Glyph::from_str(glyph);
+    }
+
+    pub fn set_color(&mut self, color: Rgba<u8>) {
+        self.color = color;
+    }
+
+    /// Color filling the cell behind the character, if any.
+    pub fn background(&self) -> Option<Rgba<u8>> {
+        self.background
+    }
+
+    pub fn set_background(&mut self, background: Option<Rgba<u8>>) {
+        self.background = background;
+    }
+}
+
+// Vertical extent, as fractions of the cell height, of the block elements
+// drawn as rectangles rather than glyphs so they tile exactly
+fn block_extent(ch: char) -> Option<(f32, f32)> {
+    match ch {
+        '▀' => Some((0.0, 0.5)),
+        '▄' => Some((0.5, 1.0)),
+        '█' => Some((0.0, 1.0)),
+        _ => None,
+    }
+}
+
+fn is_braille(ch: char) -> bool {
+    ('\u{2800}'..='\u{28ff}').contains(&ch)
+}
+
+// Draws the dots of a Braille pattern, which most monospace fonts lack, as
+// discs on a 2x4 grid spanning the cell
+fn draw_braille(img: &mut RgbaImage, ch: char, x: f32, y: f32, width: f32, height: f32, color: Rgba<u8>) -> bool {
+    if !is_braille(ch) {
+        return false;
+    }
+    let pattern = ch as u32 - 0x2800;
+    const DOTS: [(u32, f32, f32); 8] = [
+        (0x01, 0.0, 0.0), (0x02, 0.0, 1.0), (0x04, 0.0, 2.0), (0x08, 1.0, 0.0),
+        (0x10, 1.0, 1.0), (0x20, 1.0, 2.0), (0x40, 0.0, 3.0), (0x80, 1.0, 3.0),
+    ];
+    let (dot_width, dot_height) = (width / 2.0, height / 4.0);
+    let radius = (dot_width.min(dot_height) * 0.35).max(0.5);
+    for (bit, column, row) in DOTS {
+        if pattern & bit != 0 {
+            let center_x = x + (column + 0.5) * dot_width;
+            let center_y = y + (row + 0.5) * dot_height;
+            draw_filled_circle_mut(img, (center_x as i32, center_y as i32), radius.round() as i32, color);
+        }
+    }
+    true
+}
+
+fn fill_rect(img: &mut RgbaImage, x: f32, y: f32, width: f32, height: f32, color: Rgba<u8>) {
+    let (left, top) = (x.round() as i32, y.round() as i32);
+    let (right, bottom) = ((x + width).round() as i32, (y + height).round() as i32);
+    if right > left && bottom > top {
+        let rect = Rect::at(left, top).of_size((right - left) as u32, (bottom - top) as u32);
+        draw_filled_rect_mut(img, rect, color);
+    }
+}
+
+/// The result of a conversion: a grid of colored characters, stored row-major.
+///
+/// `Display` writes the characters as plain text, one line per row.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsciiArt {
+    width: u32,
+    height: u32,
+    cells: Vec<ColoredChar>,
+    cell_columns: u32,
+}
+
+impl AsciiArt {
+    /// Builds a `width` x `height` grid from its cells in row-major order.
+    ///
+    /// Panics if `cells` does not hold exactly `width * height` entries.
+    pub fn new(width: u32, height: u32, cells: Vec<ColoredChar>) -> AsciiArt {
+        assert_eq!(cells.len(), (width * height) as usize, "cell count does not match the grid size");
+        AsciiArt { width, height, cells, cell_columns: 1 }
+    }
+
+    /// Makes every cell span `columns` terminal columns, as East Asian wide
+    /// glyphs do; narrower glyphs are padded with spaces.
+    pub fn with_cell_columns(mut self, columns: u32) -> AsciiArt {
+        self.cell_columns = columns.max(1);
+        self
+    }
+
+    /// Number of terminal columns taken by each cell.
+    pub fn cell_columns(&self) -> u32 {
+        self.cell_columns
+    }
+
+    // Spaces completing the glyph of `cell` to the cell width
+    pub(crate) fn padding(&self, cell: &ColoredChar) -> String {
+        " ".repeat((self.cell_columns as usize).saturating_sub(cell.glyph().width()))
+    }
+
+    // A grid of transparent spaces
+    pub(crate) fn blank(width: u32, height: u32) -> AsciiArt {
+        AsciiArt::new(width, height, vec![ColoredChar::blank(); (width * height) as usize])
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the cell at `(x, y)`, or `None` when it is out of bounds.
+    pub fn get(&self, x: u32, y: u32) -> Option<&ColoredChar> {
+        if x < self.width && y < self.height {
+            self.cells.get((y * self.width + x) as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, x: u32, y: u32) -> Option<&mut ColoredChar> {
+        if x < self.width && y < self.height {
+            self.cells.get_mut((y * self.width + x) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Iterates over the grid one row at a time, top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &[ColoredChar]> {
+        self.cells.chunks(self.width.max(1) as usize)
+    }
+
+    /// Renders the art as text colored with 24-bit ANSI escape sequences.
+    pub fn to_ansi_string(&self) -> String {
+        self.to_ansi(ColorDepth::TrueColor)
+    }
+
+    /// Renders the art as text colored with ANSI escape sequences, each color
+    /// mapped to the nearest one available at `depth`.
+    pub fn to_ansi(&self, depth: ColorDepth) -> String {
+        if depth == ColorDepth::Mono {
+            return self.to_string();
+        }
+        let mut out = String::new();
+        for row in self.rows() {
+            // Escape sequences are only written when the colors change
+            let mut current = String::new();
+            let mut background = false;
+            for cell in row {
+                let sgr = ansi::sgr(depth, cell.color, cell.background);
+                if sgr != current {
+                    // Only a reset clears the background of the previous cell
+                    if background && cell.background.is_none() {
+                        out.push_str(ansi::RESET);
+                    }
+                    out.push_str(&sgr);
+                    current = sgr;
+                    background = cell.background.is_some();
+                }
+                out.push_str(cell.glyph());
+                out.push_str(&self.padding(cell));
+            }
+            out.push_str(ansi::RESET);
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Renders the art as a standalone HTML page inside a `<pre>` block, using
+    /// `options.font_family`, `options.font_size` and the solid or inverse background.
+    pub fn to_html(&self, options: &Options) -> String {
+        html::render(self, options)
+    }
+
+    /// Renders the art as an SVG document laid out on the same cell grid as
+    /// `to_image`, embedding the font when `options.embed_font` is set.
+    pub fn to_svg(&self, options: &Options) -> Result<String> {
+        svg::render(self, options, &options.font_chain()?)
+    }
+
+    /// Writes the art to `output_path` in `options.format`, or in the format
+    /// matching the path extension when none is set.
+    pub fn save(&self, output_path: &str, options: &Options) -> Result<()> {
+        self.save_with(output_path, options, || options.font_chain())
+    }
+
+    // `save` with the fonts of `options` loaded by `fonts`, only called for
+    // the formats that need them
+    pub(crate) fn save_with(&self, output_path: &str, options: &Options, fonts: impl FnOnce() -> Result<FontChain>) -> Result<()> {
+        let format = options.format.unwrap_or_else(|| OutputFormat::from_path(output_path));
+        // Failing to write the output is an encoding error, not an input one
+        let write = |contents: String| std::fs::write(output_path, contents).map_err(|err| Error::Encode(ImageError::IoError(err)));
+        match format {
+            OutputFormat::Text => write(self.to_string()),
+            OutputFormat::Ansi => write(self.to_ansi(options.color_depth.unwrap_or(ColorDepth::TrueColor))),
+            OutputFormat::Html => write(self.to_html(options)),
+            OutputFormat::Svg => write(svg::render(self, options, &fonts()?)?),
+            OutputFormat::Image => self.save_image_with(output_path, options, &mut GlyphAtlas::with_fonts(fonts()?)),
+        }
+    }
+
+    /// Rasterizes the art, drawing each character in its own color.
+    pub fn to_image(&self, options: &Options) -> Result<RgbaImage> {
+        self.to_image_with(options, &mut GlyphAtlas::new(options)?)
+    }
+
+    /// Rasterizes the art like `to_image`, reusing the glyphs already in
+    /// `atlas`, which must have been built for the same font options.
+    pub fn to_image_with(&self, options: &Options, atlas: &mut GlyphAtlas) -> Result<RgbaImage> {
+        let metrics = atlas.metrics(options.font_size.unwrap_or(12.0));
+        let scale = metrics.scale;
+        let line_height = scale.y.ceil() as u32;
+        let advance = metrics.width * self.cell_columns as f32;
+
+        let width = (self.width as f32 * advance).ceil() as u32;
+        let height = self.height * line_height;
+
+        // Blocks and Braille are drawn as shapes, every other glyph needs a font
+        atlas.cover(
+            self.cells
+                .iter()
+                .filter(|cell| block_extent(cell.ch()).is_none() && !is_braille(cell.ch()))
+                .map(ColoredChar::glyph),
+        )?;
+
+        // Transparent white unless there is paper, so that dropping the alpha
+        // channel leaves glyphs on white
+        let paper = options.paper().unwrap_or(Rgba([255, 255, 255, 0]));
+        let mut img = RgbaImage::from_pixel(width, height, paper);
+
+        // Draw each character with its color
+        for (y_idx, line) in self.rows().enumerate() {
+            let y = y_idx as f32 * scale.y;
+            let mut x = 0.0;
+            for colored_char in line {
+                if let Some(background) = colored_char.background {
+                    fill_rect(&mut img, x, y, advance, scale.y, background);
+                }
+                if let Some((top, bottom)) = block_extent(colored_char.ch()) {
+                    fill_rect(&mut img, x, y + top * scale.y, advance, (bottom - top) * scale.y, colored_char.color);
+                } else if !draw_braille(&mut img, colored_char.ch(), x, y, advance, scale.y, colored_char.color) {
+                    atlas.draw(&mut img, colored_char.glyph(), colored_char.color, (x, y), advance, scale);
+                }
+                x += advance;
+            }
+        }
+
+        Ok(img)
+    }
+
+    /// Rasterizes the art and saves it, the image format being chosen from the path extension.
+    pub fn save_image(&self, output_path: &str, options: &Options) -> Result<()> {
+        self.save_image_with(output_path, options, &mut GlyphAtlas::new(options)?)
+    }
+
+    /// Saves the art like `save_image`, rasterized with `to_image_with`.
+    pub fn save_image_with(&self, output_path: &str, options: &Options, atlas: &mut GlyphAtlas) -> Result<()> {
+        let img = self.to_image_with(options, atlas)?;
+        // JPEG has no alpha channel; transparent areas become white
+        if let Ok(ImageFormat::Jpeg) = ImageFormat::from_path(output_path) {
+            return DynamicImage::ImageRgba8(img).to_rgb8().save(output_path).map_err(Error::Encode);
+        }
+        img.save(output_path).map_err(Error::Encode)
+    }
+}
+
+impl fmt::Display for AsciiArt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in self.rows() {
+            for cell in row {
+                f.write_str(cell.glyph())?;
+                f.write_str(&self.padding(cell))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
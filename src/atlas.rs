@@ -0,0 +1,141 @@
+use ab_glyph::{point, Font, GlyphId, PxScale, ScaleFont};
+use image::{Rgba, RgbaImage};
+use imageproc::pixelops::weighted_sum;
+use std::collections::HashMap;
+
+use crate::fonts::{CellMetrics, FontChain};
+use crate::{Options, Result};
+
+// Coverage of one outlined character, positioned relative to the pen
+struct Layer {
+    left: i32,
+    top: i32,
+    width: u32,
+    coverage: Vec<f32>,
+}
+
+// A glyph laid out the way `draw_text_mut` does, one layer per character
+struct GlyphBitmap {
+    layers: Vec<Layer>,
+    advance: f32,
+}
+
+/// Glyph coverage bitmaps cached by font, size and glyph, so that rasterizing
+/// an art only outlines each distinct glyph once.
+///
+/// Keep one atlas around to render many frames with the same font options.
+pub struct GlyphAtlas {
+    fonts: FontChain,
+    // Keyed by font index and scale, then by glyph so that hits borrow it
+    glyphs: HashMap<(usize, u32), HashMap<Box<str>, GlyphBitmap>>,
+}
+
+impl GlyphAtlas {
+    /// An empty atlas for the fonts of `options`.
+    pub fn new(options: &Options) -> Result<GlyphAtlas> {
+        Ok(GlyphAtlas::with_fonts(options.font_chain()?))
+    }
+
+    pub(crate) fn with_fonts(fonts: FontChain) -> GlyphAtlas {
+        GlyphAtlas { fonts, glyphs: HashMap::new() }
+    }
+
+    // Makes sure some font of the atlas draws each of `glyphs`
+    pub(crate) fn cover<'g>(&mut self, glyphs: impl IntoIterator<Item = &'g str>) -> Result<()> {
+        self.fonts.cover(glyphs)
+    }
+
+    pub(crate) fn metrics(&self, font_size: f32) -> CellMetrics {
+        CellMetrics::new(self.fonts.primary(), font_size)
+    }
+
+    /// Draws `glyph` in `color` with its pen at `(x, y)`, centered on a cell
+    /// `cell_width` wide.
+    pub(crate) fn draw(&mut self, img: &mut RgbaImage, glyph: &str, color: Rgba<u8>, (x, y): (f32, f32), cell_width: f32, scale: PxScale) {
+        let Some(first) = glyph.chars().next() else {
+            return;
+        };
+        let index = self.fonts.font_index(first);
+        let glyphs = self.glyphs.entry((index, scale.y.to_bits())).or_default();
+        if !glyphs.contains_key(glyph) {
+            glyphs.insert(glyph.into(), rasterize(self.fonts.get(index), glyph, scale));
+        }
+        let bitmap = &glyphs[glyph];
+
+        let offset = ((cell_width - bitmap.advance) / 2.0).max(0.0);
+        let (pen_x, pen_y) = ((x + offset) as i32, y as i32);
+        for layer in &bitmap.layers {
+            for (i, &coverage) in layer.coverage.iter().enumerate() {
+                if coverage <= 0.0 {
+                    continue;
+                }
+                let px = pen_x + layer.left + (i as u32 % layer.width) as i32;
+                let py = pen_y + layer.top + (i as u32 / layer.width) as i32;
+                if px >= 0 && py >= 0 && (px as u32) < img.width() && (py as u32) < img.height() {
+                    let pixel = img.get_pixel_mut(px as u32, py as u32);
+                    *pixel = weighted_sum(*pixel, color, 1.0 - coverage, coverage);
+                }
+            }
+        }
+    }
+}
+
+fn rasterize<F: Font>(font: &F, glyph: &str, scale: PxScale) -> GlyphBitmap {
+    let scaled_font = font.as_scaled(scale);
+    let mut layers = Vec::new();
+    let mut pen = 0.0;
+    let mut last: Option<GlyphId> = None;
+
+    // Same layout as imageproc's text drawing, kerning quirk included
+    for ch in glyph.chars() {
+        let glyph_id = font.glyph_id(ch);
+        let positioned = glyph_id.with_scale_and_position(scale, point(pen, scaled_font.ascent()));
+        pen += scaled_font.h_advance(glyph_id);
+        if let Some(outlined) = font.outline_glyph(positioned) {
+            if let Some(last) = last {
+                pen += scaled_font.kern(glyph_id, last);
+            }
+            last = Some(glyph_id);
+            let bounds = outlined.px_bounds();
+            let width = bounds.width() as u32;
+            let height = bounds.height() as u32;
+            let mut coverage = vec![0.0; (width * height) as usize];
+            outlined.draw(|gx, gy, value| {
+                if gx < width && gy < height {
+                    coverage[(gy * width + gx) as usize] = value.clamp(0.0, 1.0);
+                }
+            });
+            layers.push(Layer { left: bounds.min.x.round() as i32, top: bounds.min.y.round() as i32, width, coverage });
+        }
+    }
+
+    let advance = glyph.chars().map(|ch| scaled_font.h_advance(font.glyph_id(ch))).sum();
+    GlyphBitmap { layers, advance }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use imageproc::drawing::draw_text_mut;
+
+    #[test]
+    fn test_matches_draw_text() {
+        let options = Options::default();
+        let mut atlas = GlyphAtlas::new(&options).unwrap();
+        let scale = PxScale::from(17.0);
+        let color = Rgba([200, 40, 90, 255]);
+
+        for glyph in ["@", "g", "%", "fi"] {
+            let mut expected = RgbaImage::from_pixel(40, 30, Rgba([255, 255, 255, 0]));
+            draw_text_mut(&mut expected, color, 5, 3, scale, atlas.fonts.primary(), glyph);
+
+            let mut actual = RgbaImage::from_pixel(40, 30, Rgba([255, 255, 255, 0]));
+            atlas.draw(&mut actual, glyph, color, (5.0, 3.0), 0.0, scale);
+            // Drawn twice to go through the cache
+            let mut cached = RgbaImage::from_pixel(40, 30, Rgba([255, 255, 255, 0]));
+            atlas.draw(&mut cached, glyph, color, (5.0, 3.0), 0.0, scale);
+            assert!(expected == actual && actual == cached, "{} differs", glyph);
+        }
+        assert_eq!(atlas.glyphs.values().map(HashMap::len).sum::<usize>(), 4);
+    }
+}
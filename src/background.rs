@@ -0,0 +1,76 @@
+use image::{Rgba, RgbaImage};
+use imageproc::filter::gaussian_blur_f32;
+use std::str::FromStr;
+
+use crate::{parse_color, ColoredChar};
+
+/// What is drawn behind the glyphs of the image, HTML and SVG outputs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Background {
+    /// Nothing: a transparent canvas, or the viewer's own page color.
+    #[default]
+    Transparent,
+    /// A single opaque color.
+    Solid(Rgba<u8>),
+    /// The color of the image under each cell, blurred over neighbouring
+    /// cells with a Gaussian of standard deviation `blur` (in cells) and
+    /// darkened by the fraction `darken`, so the glyphs stand out.
+    Image { blur: f32, darken: f32 },
+}
+
+impl Background {
+    /// `Image` with a blur of one cell, darkened by half.
+    pub const IMAGE: Background = Background::Image { blur: 1.0, darken: 0.5 };
+
+    // Gives cells without a background of their own, blank ones aside, the
+    // blurred and darkened colors of the grid
+    pub(crate) fn apply(self, columns: u32, lines: u32, cells: &mut [ColoredChar]) {
+        let Background::Image { blur, darken } = self else {
+            return;
+        };
+        let mut colors = RgbaImage::from_fn(columns, lines, |x, y| cells[(y * columns + x) as usize].color());
+        if blur > 0.0 {
+            colors = gaussian_blur_f32(&colors, blur);
+        }
+        let keep = 1.0 - darken.clamp(0.0, 1.0);
+        for (cell, color) in cells.iter_mut().zip(colors.pixels()) {
+            if cell.background().is_none() && !cell.is_blank() {
+                let [r, g, b, a] = color.0;
+                let dark = |c: u8| (c as f32 * keep).round() as u8;
+                cell.set_background(Some(Rgba([dark(r), dark(g), dark(b), a])));
+            }
+        }
+    }
+}
+
+impl FromStr for Background {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "transparent" | "none" => Ok(Background::Transparent),
+            "image" => Ok(Background::IMAGE),
+            _ => parse_color(s)
+                .map(Background::Solid)
+                .map_err(|_| format!("invalid background '{}' (expected transparent, image or a color such as #000000)", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_background_darkens_cell_colors() {
+        let mut cells = vec![ColoredChar::new('#', Rgba([200, 100, 0, 255])); 4];
+        cells[3] = ColoredChar::with_background('▀', Rgba([0, 0, 0, 255]), Rgba([1, 2, 3, 255]));
+        Background::Image { blur: 0.0, darken: 0.5 }.apply(2, 2, &mut cells);
+        assert_eq!(cells[0].background(), Some(Rgba([100, 50, 0, 255])));
+        assert_eq!(cells[3].background(), Some(Rgba([1, 2, 3, 255])));
+
+        assert_eq!("image".parse(), Ok(Background::IMAGE));
+        assert_eq!("#fff".parse(), Ok(Background::Solid(Rgba([255, 255, 255, 255]))));
+        assert!("paper".parse::<Background>().is_err());
+    }
+}
@@ -1,3 +1,15 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock, RwLock};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::fonts::{CellBitmap, CellMetrics, FontChain, FontSource};
+use crate::{Error, Result};
+
 pub const BLOCK: &[&str] = &[" ", "░", "▒", "▓", "█"];
 pub const CHINESE: &[&str] = &[
     "\u{3000}", "一", "二", "十", "人", "丁", "口", "王", "日", "木", "金", "華", "爱", "黑", "墨",
@@ -23,35 +35,366 @@ pub const RUSSIAN: &[&str] = &[
 ];
 pub const SLIGHT: &[&str] = &[
     " ", " ", ".", "`", "\"", "\\", ":", "I", "!", ">", "~", "_", "?", "[", "{", "|", ")", "(",
-    "\\", "\\\\", "/", "Y", "L", "p", "d", "a", "*", "W", "8", "%", "@", "$",
+    "\\", "\\", "/", "Y", "L", "p", "d", "a", "*", "W", "8", "%", "@", "$",
 ];
 
-pub fn from_str(s: &str, charsets: &mut Vec<&str>) {
-    match s {
-        "block" => {
-            charsets.extend_from_slice(BLOCK);
-        },
-        "chinese" => {
-            charsets.extend_from_slice(CHINESE);
-        },
-        "default" => {
-            charsets.extend_from_slice(DEFAULT);
-        },
-        "emoji" => {
-            charsets.extend_from_slice(EMOJI);
-        },
-        "russian" => {
-            charsets.extend_from_slice(RUSSIAN);
-        },
-        "slight" => {
-            charsets.extend_from_slice(SLIGHT);
-        },
-                _ => {
-            // Create a vector of &str slices from the individual characters of the input string
-            for ch in s.chars() {
-                let leaked = Box::leak(ch.to_string().into_boxed_str());
-                charsets.push(&*leaked);
-            }
+/// An ordered ramp of glyphs, from the one drawn for the darkest pixels to
+/// the one drawn for the brightest. Each glyph is a grapheme cluster.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Charset {
+    glyphs: Vec<Arc<str>>,
+    // Explicit ink coverage of each glyph, from 0 to 1, overriding the measured one
+    densities: Vec<Option<f32>>,
+    font: Option<FontSource>,
+}
+
+// A charset definition file in TOML or JSON
+#[derive(Deserialize)]
+struct CharsetFile {
+    name: Option<String>,
+    glyphs: Vec<GlyphEntry>,
+    font: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum GlyphEntry {
+    Glyph(String),
+    WithDensity { glyph: String, density: f32 },
+}
+
+const BUILTINS: [(&str, &[&str]); 6] = [
+    ("block", BLOCK),
+    ("chinese", CHINESE),
+    ("default", DEFAULT),
+    ("emoji", EMOJI),
+    ("russian", RUSSIAN),
+    ("slight", SLIGHT),
+];
+
+// Charsets known by name, seeded with the builtin tables
+fn registry() -> &'static RwLock<BTreeMap<String, Charset>> {
+    static REGISTRY: OnceLock<RwLock<BTreeMap<String, Charset>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let charsets = BUILTINS.iter().map(|(name, glyphs)| (name.to_string(), Charset::new(glyphs.iter().copied()))).collect();
+        RwLock::new(charsets)
+    })
+}
+
+impl Charset {
+    pub fn new<I, S>(glyphs: I) -> Charset
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let glyphs: Vec<Arc<str>> = glyphs.into_iter().map(|glyph| Arc::from(glyph.as_ref())).collect();
+        Charset { densities: vec![None; glyphs.len()], glyphs, font: None }
+    }
+
+    /// Splits `s` into grapheme clusters, each one becoming a glyph.
+    pub fn from_glyphs(s: &str) -> Charset {
+        Charset::new(s.graphemes(true))
+    }
+
+    /// Reads a charset definition from `path`: a TOML or JSON file (picked by
+    /// extension) with `glyphs` and optional `name` and `font`, or a plain file
+    /// whose first line holds the glyphs. The name is only used by
+    /// `register_file`.
+    ///
+    /// Each entry of `glyphs` is either a string or a `{ glyph, density }`
+    /// table fixing its ink coverage from 0 to 1; when glyphs have one, they
+    /// all must, and the charset is ordered by them.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Charset> {
+        Charset::read_file(path.as_ref()).map(|(_, charset)| charset)
+    }
+
+    /// Loads a charset file with `load` and registers it under the `name` it
+    /// gives, which must not be a builtin one. Returns that name.
+    pub fn register_file<P: AsRef<Path>>(path: P) -> Result<String> {
+        let path = path.as_ref();
+        let (name, charset) = Charset::read_file(path)?;
+        let invalid = |err: String| Error::InvalidOptions(format!("invalid charset file '{}': {}", path.display(), err));
+        let name = name.ok_or_else(|| invalid("the charset has no name".to_string()))?;
+        if BUILTINS.iter().any(|(builtin, _)| *builtin == name) {
+            return Err(invalid(format!("'{}' is the name of a builtin charset", name)));
+        }
+        Charset::register(&name, charset);
+        Ok(name)
+    }
+
+    // The name and charset defined by a file
+    fn read_file(path: &Path) -> Result<(Option<String>, Charset)> {
+        let text = std::fs::read_to_string(path)?;
+        let invalid = |err: String| Error::InvalidOptions(format!("invalid charset file '{}': {}", path.display(), err));
+
+        let extension = path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase());
+        let file: CharsetFile = match extension.as_deref() {
+            Some("toml") => toml::from_str(&text).map_err(|err| invalid(err.to_string()))?,
+            Some("json") => serde_json::from_str(&text).map_err(|err| invalid(err.to_string()))?,
+            _ => {
+                let line = text.lines().next().unwrap_or("");
+                let charset = Charset::from_glyphs(line);
+                if charset.is_empty() {
+                    return Err(invalid("the charset is empty".to_string()));
+                }
+                return Ok((None, charset));
+            },
+        };
+        if file.glyphs.is_empty() {
+            return Err(invalid("the charset is empty".to_string()));
         }
+
+        let (glyphs, densities): (Vec<Arc<str>>, Vec<Option<f32>>) = file
+            .glyphs
+            .into_iter()
+            .map(|entry| match entry {
+                GlyphEntry::Glyph(glyph) => (Arc::from(glyph), None),
+                GlyphEntry::WithDensity { glyph, density } => (Arc::from(glyph), Some(density)),
+            })
+            .unzip();
+        if let Some(density) = densities.iter().flatten().find(|density| !(0.0..=1.0).contains(*density)) {
+            return Err(invalid(format!("density {} is not between 0 and 1", density)));
+        }
+        let explicit: Option<Vec<f32>> = densities.iter().copied().collect();
+        if explicit.is_none() && densities.iter().any(Option::is_some) {
+            return Err(invalid("either every glyph or none must have a density".to_string()));
+        }
+        // Font paths are relative to the charset file
+        let font = file.font.map(|font| match font.parse() {
+            Ok(FontSource::Path(font_path)) => FontSource::Path(path.parent().unwrap_or(Path::new("")).join(font_path)),
+            Ok(font) => font,
+            Err(never) => match never {},
+        });
+        let mut charset = Charset { glyphs, densities, font };
+        if let Some(explicit) = explicit {
+            charset.sort_by(|index| explicit[index], None);
+        }
+        Ok((file.name, charset))
+    }
+
+    /// Font the charset was designed for, as given in its definition file;
+    /// used when the options do not set one.
+    pub fn font(&self) -> Option<&FontSource> {
+        self.font.as_ref()
+    }
+
+    /// Looks up a builtin or registered charset.
+    pub fn named(name: &str) -> Option<Charset> {
+        registry().read().unwrap_or_else(|e| e.into_inner()).get(name).cloned()
+    }
+
+    /// Makes `charset` available under `name`, replacing any charset of that name.
+    pub fn register(name: &str, charset: Charset) {
+        registry().write().unwrap_or_else(|e| e.into_inner()).insert(name.to_string(), charset);
+    }
+
+    /// Names of the builtin and registered charsets, sorted.
+    pub fn names() -> Vec<String> {
+        registry().read().unwrap_or_else(|e| e.into_inner()).keys().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.glyphs.is_empty()
+    }
+
+    /// Whether any glyph holds an East Asian wide grapheme, taking two
+    /// terminal columns.
+    pub fn is_wide(&self) -> bool {
+        self.glyphs().any(|glyph| glyph.graphemes(true).any(|grapheme| grapheme.width() == 2))
+    }
+
+    pub fn glyphs(&self) -> impl Iterator<Item = &str> {
+        self.glyphs.iter().map(|glyph| glyph.as_ref())
+    }
+
+    /// The glyph standing for a grey value, darkest first.
+    ///
+    /// Panics if the charset is empty.
+    pub fn find(&self, grey: u8) -> &str {
+        let index = ((self.glyphs.len() - 1) * grey as usize) / 255;
+        &self.glyphs[index]
+    }
+
+    /// `width` glyphs sampled evenly from dark to bright.
+    pub fn preview(&self, width: usize) -> String {
+        (0..width).map(|i| self.find((i * 255 / width.saturating_sub(1).max(1)) as u8)).collect()
+    }
+
+    /// Orders glyphs from lightest to darkest by their ink coverage in `fonts`,
+    /// or by their explicit density when the charset file gives one.
+    /// With a `tolerance`, glyphs whose density is within that fraction of the
+    /// densest glyph of the previous kept one are dropped.
+    pub(crate) fn sort_by_density(&mut self, fonts: &FontChain, metrics: &CellMetrics, tolerance: Option<f32>) {
+        let measured: Vec<f32> = self
+            .glyphs
+            .iter()
+            .zip(&self.densities)
+            .map(|(glyph, density)| {
+                density.unwrap_or_else(|| glyph.chars().next().map_or(0.0, |ch| CellBitmap::new(fonts.font_for(ch), ch, metrics).density()))
+            })
+            .collect();
+        self.sort_by(|index| measured[index], tolerance);
+    }
+
+    // Stable sort of the glyphs by the density of each index, dropping
+    // near-duplicates as in `sort_by_density`
+    fn sort_by(&mut self, density: impl Fn(usize) -> f32, tolerance: Option<f32>) {
+        let mut order: Vec<(usize, f32)> = (0..self.glyphs.len()).map(|index| (index, density(index))).collect();
+        order.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        if let Some(tolerance) = tolerance {
+            let max = order.last().map_or(0.0, |(_, density)| *density);
+            let mut last: Option<f32> = None;
+            order.retain(|(_, density)| {
+                let keep = last.is_none_or(|last| density - last > tolerance * max);
+                if keep {
+                    last = Some(*density);
+                }
+                keep
+            });
+        }
+
+        self.glyphs = order.iter().map(|(index, _)| self.glyphs[*index].clone()).collect();
+        self.densities = order.iter().map(|(index, _)| self.densities[*index]).collect();
+    }
+}
+
+impl Default for Charset {
+    fn default() -> Self {
+        Charset::new(DEFAULT.iter().copied())
+    }
+}
+
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.glyphs().try_for_each(|glyph| f.write_str(glyph))
+    }
+}
+
+// Strings that can only be meant as a charset name, not as glyphs
+fn looks_like_name(s: &str) -> bool {
+    s.len() > 1 && s.chars().all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '-' || ch == '_')
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    /// Looks up a named charset, loads `@path` with `Charset::load`, or
+    /// splits `glyphs:...` into glyphs. Any other string is split into glyphs
+    /// too, except lowercase words which are reported as unknown names.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix('@') {
+            return Charset::load(path).map_err(|err| err.to_string());
+        }
+        let glyphs = match s.strip_prefix("glyphs:") {
+            Some(glyphs) => glyphs,
+            None => {
+                if let Some(charset) = Charset::named(s) {
+                    return Ok(charset);
+                }
+                if looks_like_name(s) {
+                    return Err(format!(
+                        "unknown charset '{}' (available: {}; use glyphs:{} for literal glyphs)",
+                        s,
+                        Charset::names().join(", "),
+                        s
+                    ));
+                }
+                s
+            },
+        };
+        let charset = Charset::from_glyphs(glyphs);
+        if charset.is_empty() {
+            return Err("the charset is empty".to_string());
+        }
+        Ok(charset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("block".parse::<Charset>().unwrap().len(), BLOCK.len());
+        assert_eq!(" .:#".parse::<Charset>().unwrap().len(), 4);
+        let err = "blocks".parse::<Charset>().unwrap_err();
+        assert!(err.contains("unknown charset 'blocks'") && err.contains("block, chinese"), "{}", err);
+        // Words are glyphs when asked for explicitly
+        assert_eq!("glyphs:ox".parse::<Charset>().unwrap().to_string(), "ox");
+        assert_eq!("glyphs:block".parse::<Charset>().unwrap().len(), 5);
+        assert_eq!("Ox".parse::<Charset>().unwrap().to_string(), "Ox");
+
+        // Flags and ZWJ sequences are single glyphs
+        let emoji: Charset = " 🇫🇷👨‍👩‍👧".parse().unwrap();
+        assert_eq!(emoji.glyphs().collect::<Vec<_>>(), vec![" ", "🇫🇷", "👨‍👩‍👧"]);
+        assert!(emoji.is_wide() && Charset::named("chinese").unwrap().is_wide());
+        assert!(!Charset::default().is_wide() && !Charset::named("slight").unwrap().is_wide());
+        assert!(!Charset::new(["ab"]).is_wide());
+    }
+
+    #[test]
+    fn test_register() {
+        let name = format!("test-dots-{}", std::process::id());
+        Charset::register(&name, Charset::from_glyphs(" .o"));
+        assert_eq!(name.parse::<Charset>().unwrap().to_string(), " .o");
+        assert!(Charset::names().contains(&name));
+
+        // Files may not shadow builtins
+        let path = crate::temp_path("builtin-charset.toml");
+        std::fs::write(&path, "name = \"default\"\nglyphs = [\" \", \"#\"]\n").unwrap();
+        assert!(Charset::register_file(&path).is_err());
+        assert_eq!(Charset::named("default"), Some(Charset::default()));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load() {
+        let toml_path = crate::temp_path("charset.toml");
+        std::fs::write(
+            &toml_path,
+            "name = \"test-file\"\nfont = \"Mono.ttf\"\nglyphs = [{ glyph = \"#\", density = 0.9 }, { glyph = \" \", density = 0.0 }, { glyph = \".\", density = 0.1 }]\n",
+        )
+        .unwrap();
+        let charset = format!("@{}", toml_path.display()).parse::<Charset>().unwrap();
+        assert_eq!(charset.to_string(), " .#");
+        assert_eq!(charset.font(), Some(&FontSource::Path(toml_path.parent().unwrap().join("Mono.ttf"))));
+        assert_eq!(Charset::named("test-file"), None);
+
+        let json_path = crate::temp_path("charset.json");
+        std::fs::write(&json_path, r#"{"glyphs": [" ", "🇫🇷", "@"]}"#).unwrap();
+        assert_eq!(Charset::load(&json_path).unwrap().to_string(), " 🇫🇷@");
+
+        // Empty charsets, partial density lists and densities out of range
+        for invalid in [r#"{"glyphs": []}"#, r#"{"glyphs": [" ", {"glyph": "@", "density": 1.0}]}"#, r#"{"glyphs": [{"glyph": "@", "density": 1.5}]}"#] {
+            std::fs::write(&json_path, invalid).unwrap();
+            assert!(Charset::load(&json_path).is_err(), "{}", invalid);
+        }
+
+        let text_path = crate::temp_path("charset.txt");
+        std::fs::write(&text_path, " .:\nignored\n").unwrap();
+        assert_eq!(Charset::load(&text_path).unwrap().to_string(), " .:");
+
+        for path in [toml_path, json_path, text_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sort_by_density() {
+        let fonts = FontChain::new(&FontSource::default(), &[]).unwrap();
+        let metrics = CellMetrics::new(fonts.primary(), 24.0);
+
+        let mut charset = Charset::from_glyphs("@ .:.");
+        charset.sort_by_density(&fonts, &metrics, None);
+        assert_eq!(charset.to_string(), " ..:@");
+
+        charset.sort_by_density(&fonts, &metrics, Some(0.01));
+        assert_eq!(charset.to_string(), " .:@");
     }
 }
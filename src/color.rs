@@ -0,0 +1,44 @@
+use image::Rgba;
+
+/// Parses a hex color such as `#1e90ff`, `1e90ff`, `#fff` or `#1e90ff80`.
+pub fn parse_color(s: &str) -> Result<Rgba<u8>, String> {
+    let hex = s.trim().trim_start_matches('#');
+    let digits: Option<Vec<u8>> = hex.chars().map(|c| c.to_digit(16).map(|d| d as u8)).collect();
+    let digits = digits.ok_or_else(|| format!("invalid color '{}'", s))?;
+
+    match digits.as_slice() {
+        [r, g, b] => Ok(Rgba([r * 17, g * 17, b * 17, 255])),
+        [r1, r2, g1, g2, b1, b2] => Ok(Rgba([r1 * 16 + r2, g1 * 16 + g2, b1 * 16 + b2, 255])),
+        [r1, r2, g1, g2, b1, b2, a1, a2] => Ok(Rgba([r1 * 16 + r2, g1 * 16 + g2, b1 * 16 + b2, a1 * 16 + a2])),
+        _ => Err(format!("invalid color '{}' (expected #rgb, #rrggbb or #rrggbbaa)", s)),
+    }
+}
+
+// CSS/SVG notation, alpha is left out
+pub(crate) fn to_hex(color: Rgba<u8>) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+// Linear-light intensity, from 0 to 1, of a gamma-encoded sRGB channel
+pub(crate) fn linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+// Gamma-encoded sRGB channel of a linear-light intensity from 0 to 1
+pub(crate) fn encode(v: f32) -> u8 {
+    let v = v.clamp(0.0, 1.0);
+    let c = if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 };
+    (c * 255.0).round() as u8
+}
+
+// CIELAB coordinates of an sRGB color, for perceptual distances
+pub(crate) fn lab([r, g, b]: [u8; 3]) -> [f32; 3] {
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
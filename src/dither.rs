@@ -0,0 +1,224 @@
+use image::{GrayImage, Rgba};
+use std::str::FromStr;
+
+/// Dithering applied to the greyscale image before glyph lookup, quantizing
+/// it to as many grey levels as there are glyphs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dither {
+    /// Straight truncation to the nearest lower glyph.
+    #[default]
+    None,
+    FloydSteinberg,
+    Atkinson,
+    JarvisJudiceNinke,
+    /// Ordered dithering with a 2x2 Bayer matrix.
+    Bayer2,
+    /// Ordered dithering with a 4x4 Bayer matrix.
+    Bayer4,
+    /// Ordered dithering with an 8x8 Bayer matrix.
+    Bayer8,
+}
+
+// Error diffusion kernels as (dx, dy, weight), relative to the current pixel
+const FLOYD_STEINBERG: &[(isize, usize, f32)] = &[
+    (1, 0, 7.0 / 16.0),
+    (-1, 1, 3.0 / 16.0), (0, 1, 5.0 / 16.0), (1, 1, 1.0 / 16.0),
+];
+const ATKINSON: &[(isize, usize, f32)] = &[
+    (1, 0, 1.0 / 8.0), (2, 0, 1.0 / 8.0),
+    (-1, 1, 1.0 / 8.0), (0, 1, 1.0 / 8.0), (1, 1, 1.0 / 8.0),
+    (0, 2, 1.0 / 8.0),
+];
+const JARVIS_JUDICE_NINKE: &[(isize, usize, f32)] = &[
+    (1, 0, 7.0 / 48.0), (2, 0, 5.0 / 48.0),
+    (-2, 1, 3.0 / 48.0), (-1, 1, 5.0 / 48.0), (0, 1, 7.0 / 48.0), (1, 1, 5.0 / 48.0), (2, 1, 3.0 / 48.0),
+    (-2, 2, 1.0 / 48.0), (-1, 2, 3.0 / 48.0), (0, 2, 5.0 / 48.0), (1, 2, 3.0 / 48.0), (2, 2, 1.0 / 48.0),
+];
+
+impl Dither {
+    /// Quantizes `image` in place to `levels` evenly spaced grey levels.
+    ///
+    /// Each output value maps back to its own level through `find_char`.
+    pub fn apply(self, image: &mut GrayImage, levels: usize) {
+        self.apply_masked(image, levels, |_, _| false);
+    }
+
+    // `apply` where the pixels for which `is_blank` holds neither take part
+    // in error diffusion nor receive any error
+    pub(crate) fn apply_masked(self, image: &mut GrayImage, levels: usize, is_blank: impl Fn(u32, u32) -> bool) {
+        let levels = levels.clamp(2, 256);
+        match self {
+            Dither::None => {},
+            Dither::FloydSteinberg => diffuse(image, levels, FLOYD_STEINBERG, is_blank),
+            Dither::Atkinson => diffuse(image, levels, ATKINSON, is_blank),
+            Dither::JarvisJudiceNinke => diffuse(image, levels, JARVIS_JUDICE_NINKE, is_blank),
+            Dither::Bayer2 => ordered(image, levels, 1),
+            Dither::Bayer4 => ordered(image, levels, 2),
+            Dither::Bayer8 => ordered(image, levels, 3),
+        }
+    }
+
+    /// Maps the colors of a row-major grid `width` cells wide through
+    /// `quantize`, which picks the nearest palette entry, dithering the
+    /// rounding errors. `spread` is the typical distance between entries on a
+    /// channel, the amplitude of ordered dithering.
+    pub(crate) fn apply_colors(self, colors: &mut [Rgba<u8>], width: usize, spread: f32, quantize: impl Fn(Rgba<u8>) -> Rgba<u8>) {
+        match self {
+            Dither::None => colors.iter_mut().for_each(|color| *color = quantize(*color)),
+            Dither::FloydSteinberg => diffuse_colors(colors, width, FLOYD_STEINBERG, quantize),
+            Dither::Atkinson => diffuse_colors(colors, width, ATKINSON, quantize),
+            Dither::JarvisJudiceNinke => diffuse_colors(colors, width, JARVIS_JUDICE_NINKE, quantize),
+            Dither::Bayer2 => ordered_colors(colors, width, spread, 1, quantize),
+            Dither::Bayer4 => ordered_colors(colors, width, spread, 2, quantize),
+            Dither::Bayer8 => ordered_colors(colors, width, spread, 3, quantize),
+        }
+    }
+}
+
+impl FromStr for Dither {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Dither::None),
+            "floyd-steinberg" | "fs" => Ok(Dither::FloydSteinberg),
+            "atkinson" => Ok(Dither::Atkinson),
+            "jarvis-judice-ninke" | "jjn" => Ok(Dither::JarvisJudiceNinke),
+            "bayer2" => Ok(Dither::Bayer2),
+            "bayer4" => Ok(Dither::Bayer4),
+            "bayer8" => Ok(Dither::Bayer8),
+            _ => Err(format!(
+                "unknown dithering '{}' (expected none, floyd-steinberg, atkinson, jarvis-judice-ninke, bayer2, bayer4 or bayer8)",
+                s
+            )),
+        }
+    }
+}
+
+// Value of the level closest to `value` when 0..=255 is split into `levels`
+// steps, rounded up so that `find_char` lands on that same level
+fn quantize(value: f32, levels: usize) -> u8 {
+    let step = 255.0 / (levels - 1) as f32;
+    let level = (value / step).round().clamp(0.0, (levels - 1) as f32);
+    (level * step).ceil().min(255.0) as u8
+}
+
+fn diffuse(image: &mut GrayImage, levels: usize, kernel: &[(isize, usize, f32)], is_blank: impl Fn(u32, u32) -> bool) {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let mut values: Vec<f32> = image.pixels().map(|p| p[0] as f32).collect();
+    let blank: Vec<bool> = (0..width * height).map(|i| is_blank((i % width) as u32, (i / width) as u32)).collect();
+
+    for y in 0..height {
+        for x in 0..width {
+            if blank[y * width + x] {
+                continue;
+            }
+            let old = values[y * width + x];
+            let new = quantize(old, levels);
+            image.put_pixel(x as u32, y as u32, image::Luma([new]));
+
+            let error = old - new as f32;
+            for &(dx, dy, weight) in kernel {
+                let nx = x as isize + dx;
+                if nx >= 0 && (nx as usize) < width && y + dy < height && !blank[(y + dy) * width + nx as usize] {
+                    values[(y + dy) * width + nx as usize] += error * weight;
+                }
+            }
+        }
+    }
+}
+
+fn diffuse_colors(colors: &mut [Rgba<u8>], width: usize, kernel: &[(isize, usize, f32)], quantize: impl Fn(Rgba<u8>) -> Rgba<u8>) {
+    let height = colors.len() / width.max(1);
+    let mut values: Vec<[f32; 3]> = colors.iter().map(|c| [c[0] as f32, c[1] as f32, c[2] as f32]).collect();
+
+    for y in 0..height {
+        for x in 0..width {
+            let old = values[y * width + x];
+            let alpha = colors[y * width + x][3];
+            let [r, g, b] = old.map(|v| v.round().clamp(0.0, 255.0) as u8);
+            let new = quantize(Rgba([r, g, b, alpha]));
+            colors[y * width + x] = new;
+
+            for &(dx, dy, weight) in kernel {
+                let nx = x as isize + dx;
+                if nx >= 0 && (nx as usize) < width && y + dy < height {
+                    let value = &mut values[(y + dy) * width + nx as usize];
+                    for channel in 0..3 {
+                        value[channel] += (old[channel] - new[channel] as f32) * weight;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn ordered_colors(colors: &mut [Rgba<u8>], width: usize, spread: f32, order: u32, quantize: impl Fn(Rgba<u8>) -> Rgba<u8>) {
+    let size = 1 << order;
+    for (i, color) in colors.iter_mut().enumerate() {
+        let (x, y) = ((i % width) as u32, (i / width) as u32);
+        let offset = ((bayer(x % size, y % size, order) as f32 + 0.5) / (size * size) as f32 - 0.5) * spread;
+        let [r, g, b] = [0, 1, 2].map(|channel| (color[channel] as f32 + offset).round().clamp(0.0, 255.0) as u8);
+        *color = quantize(Rgba([r, g, b, color[3]]));
+    }
+}
+
+// Entry (x, y) of the 2^order x 2^order Bayer matrix
+fn bayer(x: u32, y: u32, order: u32) -> u32 {
+    let mut value = 0;
+    for bit in 0..order {
+        let (bx, by) = ((x >> bit) & 1, (y >> bit) & 1);
+        value |= ((bx ^ by) << 1 | by) << (2 * (order - 1 - bit));
+    }
+    value
+}
+
+fn ordered(image: &mut GrayImage, levels: usize, order: u32) {
+    let size = 1 << order;
+    let step = 255.0 / (levels - 1) as f32;
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let offset = (bayer(x % size, y % size, order) as f32 + 0.5) / (size * size) as f32 - 0.5;
+        pixel[0] = quantize(pixel[0] as f32 + offset * step, levels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bayer_matrix() {
+        let matrix: Vec<u32> = (0..4).map(|i| bayer(i % 2, i / 2, 1)).collect();
+        assert_eq!(matrix, vec![0, 2, 3, 1]);
+        let mut values: Vec<u32> = (0..16).map(|i| bayer(i % 4, i / 4, 2)).collect();
+        assert_eq!(&values[..4], &[0, 8, 2, 10]);
+        values.sort();
+        assert_eq!(values, (0..16).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_dithering_preserves_mean_on_flat_grey() {
+        for dither in [Dither::FloydSteinberg, Dither::Atkinson, Dither::JarvisJudiceNinke, Dither::Bayer4] {
+            let mut image = GrayImage::from_pixel(16, 16, image::Luma([128]));
+            dither.apply(&mut image, 2);
+            let white = image.pixels().filter(|p| p[0] == 255).count();
+            assert!(image.pixels().all(|p| p[0] == 0 || p[0] == 255));
+            assert!((100..=156).contains(&white), "{:?} gave {} white pixels", dither, white);
+        }
+    }
+
+    #[test]
+    fn test_diffusion_skips_blank_pixels() {
+        // A blank first column is left as it is and the rest is dithered as
+        // if it were not there
+        let ramp = |x: u32| image::Luma([40 + x as u8 * 30]);
+        let mut image = GrayImage::from_fn(6, 2, |x, _| if x == 0 { image::Luma([250]) } else { ramp(x) });
+        Dither::FloydSteinberg.apply_masked(&mut image, 2, |x, _| x == 0);
+        let mut expected = GrayImage::from_fn(5, 2, |x, _| ramp(x + 1));
+        Dither::FloydSteinberg.apply(&mut expected, 2);
+        for y in 0..2 {
+            assert_eq!(image.get_pixel(0, y)[0], 250);
+            assert_eq!((1..6).map(|x| image.get_pixel(x, y)[0]).collect::<Vec<_>>(), expected.rows().nth(y as usize).unwrap().map(|p| p[0]).collect::<Vec<_>>());
+        }
+    }
+}
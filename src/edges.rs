@@ -0,0 +1,139 @@
+use image::GrayImage;
+use imageproc::edges::canny;
+use imageproc::gradients::{horizontal_sobel, vertical_sobel};
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+/// How edges are found in the greyscale image. Thresholds apply to the Sobel
+/// gradient magnitude, which ranges from 0 to about 1442.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EdgeDetector {
+    /// Pixels whose gradient magnitude exceeds `threshold`.
+    Sobel { threshold: f32 },
+    /// Thin edges from the Canny detector with hysteresis thresholds.
+    Canny { low: f32, high: f32 },
+}
+
+impl EdgeDetector {
+    /// `Sobel` with a threshold of 200.
+    pub const SOBEL: EdgeDetector = EdgeDetector::Sobel { threshold: 200.0 };
+    /// `Canny` with thresholds of 100 and 200.
+    pub const CANNY: EdgeDetector = EdgeDetector::Canny { low: 100.0, high: 200.0 };
+}
+
+impl FromStr for EdgeDetector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sobel" => Ok(EdgeDetector::SOBEL),
+            "canny" => Ok(EdgeDetector::CANNY),
+            _ => Err(format!("unknown edge detector '{}' (expected sobel or canny)", s)),
+        }
+    }
+}
+
+/// Glyphs drawn on edges, chosen from the direction of the edge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EdgeGlyphs {
+    pub vertical: char,
+    /// Edge rising to the right.
+    pub diagonal_up: char,
+    /// Horizontal edge with the brighter side above.
+    pub horizontal: char,
+    /// Edge falling to the right.
+    pub diagonal_down: char,
+    /// Horizontal edge with the brighter side below.
+    pub horizontal_low: char,
+}
+
+impl Default for EdgeGlyphs {
+    fn default() -> Self {
+        EdgeGlyphs { vertical: '|', diagonal_up: '/', horizontal: '-', diagonal_down: '\\', horizontal_low: '_' }
+    }
+}
+
+impl FromStr for EdgeGlyphs {
+    type Err = String;
+
+    /// Parses five glyphs, in the order vertical, diagonal up, horizontal,
+    /// diagonal down and low horizontal, e.g. `|/-\_`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.chars().collect::<Vec<char>>().as_slice() {
+            &[vertical, diagonal_up, horizontal, diagonal_down, horizontal_low] => {
+                Ok(EdgeGlyphs { vertical, diagonal_up, horizontal, diagonal_down, horizontal_low })
+            },
+            _ => Err(format!("expected five edge glyphs such as |/-\\_, got '{}'", s)),
+        }
+    }
+}
+
+/// Edge-aware glyph selection: cells on an edge get a directional glyph
+/// instead of the one matching their brightness.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Edges {
+    pub detector: EdgeDetector,
+    pub glyphs: EdgeGlyphs,
+}
+
+impl Default for Edges {
+    fn default() -> Self {
+        Edges { detector: EdgeDetector::SOBEL, glyphs: EdgeGlyphs::default() }
+    }
+}
+
+impl Edges {
+    /// Directional glyph for every pixel of `image`, `None` off edges.
+    pub(crate) fn detect(&self, image: &GrayImage) -> Vec<Option<char>> {
+        let gx = horizontal_sobel(image);
+        let gy = vertical_sobel(image);
+        let mask = match self.detector {
+            EdgeDetector::Canny { low, high } => Some(canny(image, low, high)),
+            EdgeDetector::Sobel { .. } => None,
+        };
+
+        image
+            .enumerate_pixels()
+            .map(|(x, y, _)| {
+                let (dx, dy) = (gx.get_pixel(x, y)[0] as f32, gy.get_pixel(x, y)[0] as f32);
+                let on_edge = match self.detector {
+                    EdgeDetector::Sobel { threshold } => dx.hypot(dy) > threshold,
+                    EdgeDetector::Canny { .. } => mask.as_ref().is_some_and(|mask| mask.get_pixel(x, y)[0] > 0),
+                };
+                on_edge.then(|| self.glyph(dx, dy))
+            })
+            .collect()
+    }
+
+    // The edge runs perpendicular to the gradient (dx, dy), with y pointing down
+    fn glyph(&self, dx: f32, dy: f32) -> char {
+        let angle = dy.atan2(dx).rem_euclid(PI);
+        let sector = ((angle / (PI / 4.0)).round() as u32) % 4;
+        match sector {
+            0 => self.glyphs.vertical,
+            1 => self.glyphs.diagonal_up,
+            2 if dy > 0.0 => self.glyphs.horizontal_low,
+            2 => self.glyphs.horizontal,
+            _ => self.glyphs.diagonal_down,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glyph_follows_edge_direction() {
+        let edges = Edges::default();
+        assert_eq!(edges.glyph(1.0, 0.0), '|');
+        assert_eq!(edges.glyph(-1.0, 0.0), '|');
+        assert_eq!(edges.glyph(0.0, 1.0), '_');
+        assert_eq!(edges.glyph(0.0, -1.0), '-');
+        assert_eq!(edges.glyph(1.0, 1.0), '/');
+        assert_eq!(edges.glyph(1.0, -1.0), '\\');
+
+        assert_eq!("Canny".parse(), Ok(EdgeDetector::CANNY));
+        assert!("prewitt".parse::<EdgeDetector>().is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The input image, or a charset or palette file, could not be opened or read.
+    Io(std::io::Error),
+    /// The input image could not be decoded.
+    Decode(image::ImageError),
+    /// The font used to lay out the ASCII art could not be loaded.
+    Font(ab_glyph::InvalidFont),
+    /// A font file could not be read.
+    FontFile(std::path::PathBuf, std::io::Error),
+    /// No font, bundled or installed, has these characters.
+    MissingGlyphs(Vec<char>),
+    /// The rendered ASCII art could not be encoded or written.
+    Encode(image::ImageError),
+    /// The conversion options cannot be used, e.g. an empty charset.
+    InvalidOptions(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Decode(err) => write!(f, "failed to decode image: {}", err),
+            Error::Font(err) => write!(f, "failed to load font: {}", err),
+            Error::FontFile(path, err) => write!(f, "failed to read font {}: {}", path.display(), err),
+            Error::MissingGlyphs(chars) => {
+                // The first few are enough to pick a font
+                let mut listed: Vec<String> = chars.iter().take(8).map(|ch| format!("'{}' (U+{:04X})", ch, *ch as u32)).collect();
+                if chars.len() > listed.len() {
+                    listed.push(format!("{} more", chars.len() - listed.len()));
+                }
+                write!(f, "no font has glyphs for {}; pass a font that does with --fallback-font", listed.join(", "))
+            },
+            Error::Encode(err) => write!(f, "failed to save output: {}", err),
+            Error::InvalidOptions(msg) => write!(f, "invalid options: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) | Error::FontFile(_, err) => Some(err),
+            Error::Decode(err) | Error::Encode(err) => Some(err),
+            Error::Font(err) => Some(err),
+            Error::MissingGlyphs(_) | Error::InvalidOptions(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<ab_glyph::InvalidFont> for Error {
+    fn from(err: ab_glyph::InvalidFont) -> Self {
+        Error::Font(err)
+    }
+}
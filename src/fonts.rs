@@ -0,0 +1,324 @@
+use ab_glyph::{point, Font, FontArc, PxScale, ScaleFont};
+use std::borrow::Cow;
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::{Error, Result};
+
+const ANONYMOUS_PRO: &[u8] = include_bytes!("fonts/Anonymous Pro.ttf");
+const DEJAVU_SANS: &[u8] = include_bytes!("fonts/DejaVuSans.ttf");
+const HACK_NERD_FONT: &[u8] = include_bytes!("fonts/HackNerdFont-Regular.ttf");
+const HACK_NERD_FONT_MONO: &[u8] = include_bytes!("fonts/HackNerdFontMono-Regular.ttf");
+
+/// The fonts shipped with the crate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BundledFont {
+    #[default]
+    AnonymousPro,
+    DejaVuSans,
+    HackNerdFont,
+    HackNerdFontMono,
+}
+
+impl BundledFont {
+    fn data(self) -> &'static [u8] {
+        match self {
+            BundledFont::AnonymousPro => ANONYMOUS_PRO,
+            BundledFont::DejaVuSans => DEJAVU_SANS,
+            BundledFont::HackNerdFont => HACK_NERD_FONT,
+            BundledFont::HackNerdFontMono => HACK_NERD_FONT_MONO,
+        }
+    }
+}
+
+impl FromStr for BundledFont {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "anonymous-pro" => Ok(BundledFont::AnonymousPro),
+            "dejavu-sans" => Ok(BundledFont::DejaVuSans),
+            "hack-nerd-font" => Ok(BundledFont::HackNerdFont),
+            "hack-nerd-font-mono" => Ok(BundledFont::HackNerdFontMono),
+            _ => Err(format!(
+                "unknown bundled font '{}' (expected anonymous-pro, dejavu-sans, hack-nerd-font or hack-nerd-font-mono)",
+                s
+            )),
+        }
+    }
+}
+
+/// A TrueType or OpenType font used to lay out and rasterize the art.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FontSource {
+    /// One of the fonts shipped with the crate.
+    Bundled(BundledFont),
+    /// A font file read when rendering.
+    Path(PathBuf),
+    /// The contents of a font file.
+    Bytes(Vec<u8>),
+}
+
+impl Default for FontSource {
+    fn default() -> Self {
+        FontSource::Bundled(BundledFont::default())
+    }
+}
+
+impl FontSource {
+    /// The raw font file.
+    pub(crate) fn data(&self) -> Result<Cow<'_, [u8]>> {
+        Ok(match self {
+            FontSource::Bundled(font) => Cow::Borrowed(font.data()),
+            FontSource::Path(path) => Cow::Owned(std::fs::read(path).map_err(|err| Error::FontFile(path.clone(), err))?),
+            FontSource::Bytes(bytes) => Cow::Borrowed(bytes),
+        })
+    }
+
+    fn load(&self) -> Result<FontArc> {
+        Ok(match self {
+            FontSource::Bundled(font) => FontArc::try_from_slice(font.data())?,
+            _ => FontArc::try_from_vec(self.data()?.into_owned())?,
+        })
+    }
+}
+
+impl FromStr for FontSource {
+    type Err = Infallible;
+
+    /// A bundled font name, or else the path of a font file.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s.parse::<BundledFont>() {
+            Ok(font) => FontSource::Bundled(font),
+            Err(_) => FontSource::Path(PathBuf::from(s)),
+        })
+    }
+}
+
+/// Fonts searched in order for each glyph: the main font, then the fallback
+/// fonts of the options, then the bundled Hack Nerd Font Mono and DejaVu Sans,
+/// then the installed fonts added by `cover`.
+#[derive(Clone)]
+pub(crate) struct FontChain {
+    fonts: Vec<FontArc>,
+}
+
+impl FontChain {
+    pub fn new(font: &FontSource, fallbacks: &[FontSource]) -> Result<FontChain> {
+        let mut fonts = vec![font.load()?];
+        for fallback in fallbacks {
+            fonts.push(fallback.load()?);
+        }
+        fonts.push(FontArc::try_from_slice(HACK_NERD_FONT_MONO)?);
+        fonts.push(FontArc::try_from_slice(DEJAVU_SANS)?);
+        Ok(FontChain { fonts })
+    }
+
+    /// The font setting the cell size.
+    pub fn primary(&self) -> &FontArc {
+        &self.fonts[0]
+    }
+
+    /// The first font of the chain having a glyph for `ch`, or the primary
+    /// font when none has.
+    pub fn font_for(&self, ch: char) -> &FontArc {
+        &self.fonts[self.font_index(ch)]
+    }
+
+    /// Position in the chain of the font `font_for` picks.
+    pub fn font_index(&self, ch: char) -> usize {
+        self.fonts.iter().position(|font| draws(font, ch)).unwrap_or(0)
+    }
+
+    /// Appends installed fonts to the chain until some font draws the first
+    /// character of each of `glyphs`, failing with the characters none does.
+    pub fn cover<'g>(&mut self, glyphs: impl IntoIterator<Item = &'g str>) -> Result<()> {
+        let mut missing: Vec<char> = glyphs
+            .into_iter()
+            .filter_map(|glyph| glyph.chars().next())
+            .filter(|&ch| !ch.is_whitespace() && !self.fonts.iter().any(|font| draws(font, ch)))
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+
+        // Installed fonts are only read when the chain lacks something
+        for path in system_fonts() {
+            if missing.is_empty() {
+                break;
+            }
+            let Some(font) = std::fs::read(path).ok().and_then(|data| FontArc::try_from_vec(data).ok()) else {
+                continue;
+            };
+            if missing.iter().any(|&ch| draws(&font, ch)) {
+                missing.retain(|&ch| !draws(&font, ch));
+                self.fonts.push(font);
+            }
+        }
+        if missing.is_empty() { Ok(()) } else { Err(Error::MissingGlyphs(missing)) }
+    }
+
+    pub fn get(&self, index: usize) -> &FontArc {
+        &self.fonts[index]
+    }
+}
+
+// Whether `font` has an outline for `ch`; bitmap-only glyphs such as those
+// of color emoji fonts cannot be rasterized
+fn draws(font: &FontArc, ch: char) -> bool {
+    let id = font.glyph_id(ch);
+    id.0 != 0 && (ch.is_whitespace() || font.outline(id).is_some())
+}
+
+// Font files of the usual system and user font directories, sorted
+fn system_fonts() -> &'static [PathBuf] {
+    static FONTS: OnceLock<Vec<PathBuf>> = OnceLock::new();
+    FONTS.get_or_init(|| {
+        let mut dirs = vec![
+            PathBuf::from("/usr/share/fonts"),
+            PathBuf::from("/usr/local/share/fonts"),
+            PathBuf::from("/Library/Fonts"),
+            PathBuf::from("/System/Library/Fonts"),
+        ];
+        if let Some(home) = std::env::var_os("HOME").map(PathBuf::from) {
+            dirs.extend([home.join(".local/share/fonts"), home.join(".fonts"), home.join("Library/Fonts")]);
+        }
+        if let Some(windir) = std::env::var_os("WINDIR").map(PathBuf::from) {
+            dirs.push(windir.join("Fonts"));
+        }
+        let mut fonts = Vec::new();
+        for dir in dirs {
+            collect_fonts(&dir, &mut fonts);
+        }
+        fonts.sort();
+        fonts
+    })
+}
+
+fn collect_fonts(dir: &Path, fonts: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        let extension = path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase());
+        if path.is_dir() {
+            collect_fonts(&path, fonts);
+        } else if matches!(extension.as_deref(), Some("ttf" | "otf" | "ttc")) {
+            fonts.push(path);
+        }
+    }
+}
+
+/// Geometry of one character cell in the rendered outputs.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct CellMetrics {
+    pub scale: PxScale,
+    /// Horizontal advance of a cell, the width of `W` in the primary font.
+    pub width: f32,
+    /// Distance between two rows.
+    pub height: f32,
+    /// Distance from the top of a cell to the baseline.
+    pub ascent: f32,
+}
+
+impl CellMetrics {
+    pub fn new<F: Font>(font: &F, font_size: f32) -> CellMetrics {
+        let scale = PxScale::from(font_size);
+        let scaled_font = font.as_scaled(scale);
+        CellMetrics {
+            scale,
+            width: scaled_font.h_advance(font.glyph_id('W')),
+            height: scale.y,
+            ascent: scaled_font.ascent(),
+        }
+    }
+}
+
+/// Coverage bitmap of one glyph drawn in a cell on its baseline.
+pub(crate) struct CellBitmap {
+    pub width: u32,
+    pub height: u32,
+    /// Row-major coverage, from 0 (empty) to 1 (fully inked).
+    pub coverage: Vec<f32>,
+}
+
+impl CellBitmap {
+    pub fn new<F: Font>(font: &F, ch: char, metrics: &CellMetrics) -> CellBitmap {
+        let width = metrics.width.ceil().max(1.0) as u32;
+        let height = metrics.height.ceil().max(1.0) as u32;
+        let mut coverage = vec![0.0; (width * height) as usize];
+
+        // Glyphs missing from the font are left blank rather than drawn as .notdef
+        let glyph = font.glyph_id(ch).with_scale_and_position(metrics.scale, point(0.0, metrics.ascent));
+        let outlined = if glyph.id.0 == 0 { None } else { font.outline_glyph(glyph) };
+        if let Some(outlined) = outlined {
+            let bounds = outlined.px_bounds();
+            outlined.draw(|x, y, c| {
+                let px = x as i32 + bounds.min.x as i32;
+                let py = y as i32 + bounds.min.y as i32;
+                if px >= 0 && py >= 0 && (px as u32) < width && (py as u32) < height {
+                    let pixel = &mut coverage[(py as u32 * width + px as u32) as usize];
+                    *pixel = (*pixel + c).min(1.0);
+                }
+            });
+        }
+
+        CellBitmap { width, height, coverage }
+    }
+
+    /// Mean coverage over the whole cell.
+    pub fn density(&self) -> f32 {
+        self.coverage.iter().sum::<f32>() / self.coverage.len() as f32
+    }
+
+    /// Mean coverage over each block of a `columns` x `rows` grid, row-major.
+    pub fn downsample(&self, columns: u32, rows: u32) -> Vec<f32> {
+        let mut sums = vec![0.0; (columns * rows) as usize];
+        let mut counts = vec![0u32; (columns * rows) as usize];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let block = ((y * rows / self.height) * columns + x * columns / self.width) as usize;
+                sums[block] += self.coverage[(y * self.width + x) as usize];
+                counts[block] += 1;
+            }
+        }
+        sums.iter().zip(counts).map(|(sum, count)| if count > 0 { sum / count as f32 } else { 0.0 }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_falls_back_per_glyph() {
+        let chain = FontChain::new(&FontSource::default(), &[]).unwrap();
+        assert!(chain.font_for('W').glyph_id('W').0 != 0);
+        // Shades are missing from Anonymous Pro, Braille from both it and Hack
+        assert!(chain.font_for('▒').glyph_id('▒').0 != 0);
+        assert!(chain.font_for('\u{2847}').glyph_id('\u{2847}').0 != 0);
+        assert!(FontChain::new(&FontSource::default(), &[FontSource::Bytes(vec![0; 16])]).is_err());
+
+        let chain = FontChain::new(&"dejavu-sans".parse().unwrap(), &[]).unwrap();
+        assert!(chain.primary().glyph_id('\u{2847}').0 != 0);
+        assert_eq!("fonts/My Font.otf".parse(), Ok(FontSource::Path(PathBuf::from("fonts/My Font.otf"))));
+    }
+
+    #[test]
+    fn test_missing_font_file() {
+        let path = crate::temp_path("missing-font.ttf");
+        let err = FontChain::new(&FontSource::Path(path.clone()), &[]).err().unwrap();
+        assert!(matches!(&err, Error::FontFile(missing, _) if *missing == path));
+    }
+
+    #[test]
+    fn test_cover_reports_missing_glyphs() {
+        let mut chain = FontChain::new(&FontSource::default(), &[]).unwrap();
+        assert!(chain.cover([" ", "W", "▒"]).is_ok());
+        // A private use character no font draws
+        let err = chain.cover(["W", "\u{10FFFD}"]).unwrap_err();
+        assert!(matches!(&err, Error::MissingGlyphs(chars) if chars == &['\u{10FFFD}']));
+        assert!(err.to_string().contains("U+10FFFD"), "{}", err);
+    }
+}
@@ -0,0 +1,49 @@
+use std::path::Path;
+use std::str::FromStr;
+
+/// How the ASCII art is written to `Options::output_path`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Plain characters, one line per row.
+    Text,
+    /// Characters colored with 24-bit ANSI escape sequences.
+    Ansi,
+    /// A self-contained HTML document with one colored span per run of characters.
+    Html,
+    /// A scalable SVG image with one text element per run of characters.
+    Svg,
+    /// A raster image; its encoding is chosen from the path extension.
+    Image,
+}
+
+impl OutputFormat {
+    /// Picks the format matching the extension of `path`, falling back to `Image`.
+    pub fn from_path(path: &str) -> OutputFormat {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("txt") => OutputFormat::Text,
+            Some("ans") | Some("ansi") => OutputFormat::Ansi,
+            Some("html") | Some("htm") => OutputFormat::Html,
+            Some("svg") => OutputFormat::Svg,
+            _ => OutputFormat::Image,
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" | "txt" => Ok(OutputFormat::Text),
+            "ansi" | "ans" => Ok(OutputFormat::Ansi),
+            "html" | "htm" => Ok(OutputFormat::Html),
+            "svg" => Ok(OutputFormat::Svg),
+            "image" => Ok(OutputFormat::Image),
+            _ => Err(format!("unknown output format '{}' (expected text, ansi, html, svg or image)", s)),
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::color::to_hex;
+use crate::{AsciiArt, ColoredChar, Options};
+
+// Characters that cannot appear verbatim in HTML text or attributes
+pub(crate) fn escape(text: &str, out: &mut String) {
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(ch),
+        }
+    }
+}
+
+// A font family for a style element, which is raw text: characters that
+// could end the rule or the element are dropped rather than escaped
+pub(crate) fn css_font_family(font_family: &str) -> String {
+    font_family.chars().filter(|ch| !"<>{};".contains(*ch)).collect()
+}
+
+fn span_style(cell: &ColoredChar) -> String {
+    match cell.background() {
+        Some(background) => format!("color:{};background-color:{}", to_hex(cell.color()), to_hex(background)),
+        None => format!("color:{}", to_hex(cell.color())),
+    }
+}
+
+pub(crate) fn render(art: &AsciiArt, options: &Options) -> String {
+    let font_size = options.font_size.unwrap_or(12.0);
+
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>ASCII art</title>\n<style>\n");
+    out.push_str("pre {\n");
+    out.push_str(&format!("  font-family: {};\n", css_font_family(&options.font_family)));
+    out.push_str(&format!("  font-size: {}px;\n  line-height: 1;\n", font_size));
+    if let Some(background) = options.paper() {
+        out.push_str(&format!("  background-color: {};\n", to_hex(background)));
+    }
+    out.push_str("}\n</style>\n</head>\n<body>\n<pre>");
+
+    // One span per run of identically colored characters on a row
+    for row in art.rows() {
+        let mut cells = row.iter().peekable();
+        while let Some(cell) = cells.next() {
+            let style = span_style(cell);
+            out.push_str(&format!("<span style=\"{}\">", style));
+            escape(cell.glyph(), &mut out);
+            out.push_str(&art.padding(cell));
+            while let Some(next) = cells.next_if(|next| span_style(next) == style) {
+                escape(next.glyph(), &mut out);
+                out.push_str(&art.padding(next));
+            }
+            out.push_str("</span>");
+        }
+        out.push('\n');
+    }
+
+    out.push_str("</pre>\n</body>\n</html>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_runs_are_merged_and_escaped() {
+        let red = Rgba([255, 0, 0, 255]);
+        let blue = Rgba([0, 0, 255, 255]);
+        let art = AsciiArt::new(3, 1, vec![
+            ColoredChar::new('<', red),
+            ColoredChar::new('&', red),
+            ColoredChar::new('"', blue),
+        ]);
+
+        let html = render(&art, &Options::default());
+        assert!(html.contains("<pre><span style=\"color:#ff0000\">&lt;&amp;</span><span style=\"color:#0000ff\">&quot;</span>\n</pre>"));
+        assert!(!html.contains("background-color"));
+    }
+}
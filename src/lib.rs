@@ -1,117 +1,338 @@
-use image::{ImageReader, DynamicImage, GrayImage, GenericImageView , Rgba,  RgbaImage};
-use imageproc::drawing::draw_text_mut;
-use ansi_term::Color;
-use ab_glyph::{FontRef, PxScale, Font, ScaleFont};
+use image::{ImageReader, DynamicImage, GrayImage, GenericImageView, Rgba};
+use ab_glyph::{PxScale, Font, ScaleFont};
 use rand::Rng;
+use std::io::{BufReader, Cursor, Read, Seek, Write};
 use std::thread::sleep;
 use std::time::Duration;
 
 use std::borrow::Cow;
+use std::cell::OnceCell;
 
-mod charsets;
-
-
-#[derive(Clone)]
-pub struct ColoredChar {
-    ch: char,
-    color: Rgba<u8>,
-}
+use fonts::{CellMetrics, FontChain};
+use shapes::{GlyphShapes, SHAPE_COLUMNS, SHAPE_ROWS};
 
+mod alpha;
+mod ansi;
+mod art;
+mod atlas;
+mod background;
+mod charsets;
+mod color;
+mod dither;
+mod edges;
+mod error;
+mod fonts;
+mod format;
+mod html;
+mod mode;
+mod palette;
+mod resize;
+mod shapes;
+mod svg;
+mod tone;
+
+pub use alpha::Alpha;
+pub use ansi::ColorDepth;
+pub use art::{AsciiArt, ColoredChar};
+pub use atlas::GlyphAtlas;
+pub use background::Background;
+pub use charsets::Charset;
+pub use color::parse_color;
+pub use dither::Dither;
+pub use edges::{EdgeDetector, EdgeGlyphs, Edges};
+pub use error::{Error, Result};
+pub use fonts::{BundledFont, FontSource};
+pub use format::OutputFormat;
+pub use mode::RenderMode;
+pub use palette::Palette;
+pub use resize::ResizeFilter;
+pub use shapes::ShapeMetric;
+pub use tone::{Levels, Luma, Tone};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Pixel {
     pub r: u8,
     pub g: u8,
     pub b: u8,
     pub a: u8,
     pub grey: u8,
-    pub position: Vec<(u32, u32)>,
 }
 
-struct Pixels {
+impl Pixel {
+    pub fn color(&self) -> Rgba<u8> {
+        Rgba([self.r, self.g, self.b, self.a])
+    }
+}
+
+/// Color and grey values of the resized image, stored as a dense row-major
+/// grid. There is one entry per cell, or several when the render mode samples
+/// more than one pixel per cell.
+#[derive(Clone, Debug, Default)]
+pub struct Pixels {
+    width: u32,
+    height: u32,
     pixels: Vec<Pixel>,
 }
 
 impl Pixels {
-    fn get_pixel(&self, x: u32, y: u32) -> &Pixel {
-        self.pixels.iter().find(|pixel| pixel.position.contains(&(x, y))).unwrap()
+    fn new(width: u32, height: u32, pixels: Vec<Pixel>) -> Pixels {
+        debug_assert_eq!(pixels.len(), (width * height) as usize);
+        Pixels { width, height, pixels }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the cell at `(x, y)`, or `None` when it is out of bounds.
+    pub fn get(&self, x: u32, y: u32) -> Option<&Pixel> {
+        if x < self.width && y < self.height {
+            self.pixels.get((y * self.width + x) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the cell at `(x, y)`.
+    ///
+    /// Panics if the coordinates are out of bounds.
+    pub fn get_pixel(&self, x: u32, y: u32) -> &Pixel {
+        &self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// Iterates over the grid one row at a time, top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &[Pixel]> {
+        self.pixels.chunks(self.width.max(1) as usize)
     }
 }
 
 pub trait ASCII {
-    fn reader(&mut self) -> DynamicImage;
+    fn reader(&mut self) -> Result<DynamicImage>;
     fn convert_to_greyscale(&mut self, image: &DynamicImage) -> GrayImage;
-    fn convert_to_ascii(&self, image: GrayImage) -> Vec<Vec<ColoredChar>>;
-    fn save_image(&self, ascii: Vec<Vec<ColoredChar>>, output_path: &str);
+    fn convert_to_ascii(&self, image: GrayImage) -> Result<AsciiArt>;
+    fn save_image(&self, ascii: &AsciiArt, output_path: &str) -> Result<()>;
 }
 
 
+// Where `reader` gets the image to convert from
+enum Source {
+    Path(String),
+    Image(DynamicImage),
+}
+
 pub struct ASCIIImage<'a> {
-    image_path: String,
+    source: Source,
     options: Options<'a>,
     pixels: Pixels,
     nb_chars_per_line: u32,
     nb_chars_per_column: u32,
+    fonts: OnceCell<FontChain>,
+    glyph_shapes: OnceCell<GlyphShapes>,
 }
 
 pub struct Options<'a> {
     pub columns: Option<u32>,
     pub lines: Option<u32>,
     pub color: bool,
+    /// Colors available when printing with `color`, detected from the
+    /// environment when `None`; saved ANSI files default to 24-bit colors.
+    pub color_depth: Option<ColorDepth>,
     pub print: bool,
-    pub charsets: Cow<'a, str>,
+    pub charsets: Charset,
     pub output_path: Cow<'a, str>,
     pub font_size: Option<f32>,
+    /// Format of the saved output; guessed from `output_path` when `None`.
+    pub format: Option<OutputFormat>,
+    /// CSS font family of the HTML and SVG outputs.
+    pub font_family: Cow<'a, str>,
+    /// Colors every cell is limited to, in all outputs.
+    pub palette: Option<Palette>,
+    /// Dithering of the cell colors when mapping them to `palette`.
+    pub palette_dither: Dither,
+    /// Background of the image, HTML and SVG outputs.
+    pub background: Background,
+    /// Dark glyphs on light paper: the grey levels are inverted as with
+    /// `tone.invert`, so that dense glyphs stand for dark pixels, and outputs
+    /// are drawn on white unless the background is a solid color.
+    pub inverse: bool,
+    /// Embed the font in SVG output so it renders the same everywhere.
+    pub embed_font: bool,
+    /// Font laying out and rasterizing the art; when `None`, the font of the
+    /// charset definition or else the bundled Anonymous Pro.
+    pub font: Option<FontSource>,
+    /// Fonts searched, after `font` and before the bundled fallbacks, for
+    /// glyphs to rasterize.
+    pub fallback_fonts: Vec<FontSource>,
+    pub mode: RenderMode,
+    /// Filter scaling the image down to the character grid.
+    pub resize_filter: ResizeFilter,
+    /// Brightness above which a Braille dot is lit, unless dithering.
+    pub threshold: u8,
+    /// Blank cells or a matte color for the transparent parts of the image.
+    pub alpha: Alpha,
+    /// Formula reducing pixel colors to grey levels.
+    pub luma: Luma,
+    /// Brightness, contrast and levels adjustments of the grey levels.
+    pub tone: Tone,
+    /// Dithering of the grey levels before glyph lookup.
+    pub dither: Dither,
+    /// Draw directional glyphs along edges in charset mode.
+    pub edges: Option<Edges>,
+    /// Similarity measure between cells and glyphs in shape mode.
+    pub shape_metric: ShapeMetric,
+    /// Reorder the charset from lightest to darkest by measured glyph ink coverage.
+    pub sort_charset: bool,
+    /// When sorting, drop glyphs whose density is within this fraction of the
+    /// densest glyph from the previous one.
+    pub dedupe_density: Option<f32>,
+}
+
+impl Options<'_> {
+    // The font of the options, else of the charset, else Anonymous Pro
+    pub(crate) fn font_source(&self) -> FontSource {
+        self.font.as_ref().or(self.charsets.font()).cloned().unwrap_or_default()
+    }
+
+    // Color filling the whole output, if any
+    pub(crate) fn paper(&self) -> Option<Rgba<u8>> {
+        match self.background {
+            Background::Solid(color) => Some(color),
+            _ if self.inverse => Some(Rgba([255, 255, 255, 255])),
+            _ => None,
+        }
+    }
+
+    // Tone adjustments, inverted for `inverse` paper
+    pub(crate) fn tone(&self) -> Tone {
+        Tone { invert: self.tone.invert || self.inverse, ..self.tone }
+    }
+
+    pub(crate) fn font_chain(&self) -> Result<FontChain> {
+        FontChain::new(&self.font_source(), &self.fallback_fonts)
+    }
+
+    pub(crate) fn cell_metrics(&self, fonts: &FontChain) -> CellMetrics {
+        CellMetrics::new(fonts.primary(), self.font_size.unwrap_or(12.0))
+    }
+}
+
+impl Default for Options<'_> {
+    fn default() -> Self {
+        Options {
+            columns: None,
+            lines: None,
+            color: false,
+            color_depth: None,
+            print: false,
+            charsets: Charset::default(),
+            output_path: Cow::Borrowed(""),
+            font_size: None,
+            format: None,
+            font_family: Cow::Borrowed("monospace"),
+            palette: None,
+            palette_dither: Dither::None,
+            background: Background::Transparent,
+            inverse: false,
+            embed_font: false,
+            font: None,
+            fallback_fonts: Vec::new(),
+            mode: RenderMode::Charset,
+            resize_filter: ResizeFilter::Area,
+            threshold: 128,
+            alpha: Alpha::THRESHOLD,
+            luma: Luma::Rec709,
+            tone: Tone::default(),
+            dither: Dither::None,
+            edges: None,
+            shape_metric: ShapeMetric::Ssd,
+            sort_charset: false,
+            dedupe_density: None,
+        }
+    }
 }
 
 impl <'a>ASCIIImage<'a> {
-    pub fn new(image_path: String, options: Options<'a>) -> ASCIIImage {
+    pub fn new(image_path: String, options: Options<'a>) -> ASCIIImage<'a> {
+        ASCIIImage::with_source(Source::Path(image_path), options)
+    }
+
+    /// Converts an image that has already been decoded.
+    pub fn from_image(image: DynamicImage, options: Options<'a>) -> ASCIIImage<'a> {
+        ASCIIImage::with_source(Source::Image(image), options)
+    }
+
+    /// Decodes an encoded image held in memory, guessing its format from its content.
+    pub fn from_bytes(bytes: &[u8], options: Options<'a>) -> Result<ASCIIImage<'a>> {
+        ASCIIImage::from_reader(Cursor::new(bytes), options)
+    }
+
+    /// Decodes an encoded image from any seekable reader, guessing its format from its content.
+    pub fn from_reader<R: Read + Seek>(reader: R, options: Options<'a>) -> Result<ASCIIImage<'a>> {
+        let image = ImageReader::new(BufReader::new(reader))
+            .with_guessed_format()?
+            .decode()
+            .map_err(Error::Decode)?;
+        Ok(ASCIIImage::from_image(image, options))
+    }
+
+    fn with_source(source: Source, options: Options<'a>) -> ASCIIImage<'a> {
         ASCIIImage {
-            image_path,
+            source,
             options,
-            pixels: Pixels { pixels: Vec::new() },
+            pixels: Pixels::default(),
             nb_chars_per_column: 0,
             nb_chars_per_line: 0,
+            fonts: OnceCell::new(),
+            glyph_shapes: OnceCell::new(),
         }
     }
 
+    /// Per-cell values computed by the last call to `convert_to_greyscale`.
+    pub fn pixels(&self) -> &Pixels {
+        &self.pixels
+    }
+
     pub fn pixel_to_greyscale(&self, pixel: Rgba<u8>) -> u8 {
-        let r = pixel[0];
-        let g = pixel[1];
-        let b = pixel[2];
-        // Apply the sRGB color space conversion formula: https://en.wikipedia.org/wiki/Grayscale
-        ((0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32)) as u8
+        self.options.luma.grey(pixel)
     }
 
-    pub fn save_greyscale(&mut self, output_path: &str) -> Result<(), image::ImageError> {
-        let image = self.reader();
+    pub fn save_greyscale(&mut self, output_path: &str) -> Result<()> {
+        let image = self.reader()?;
         let greyscale_image = self.convert_to_greyscale(&image);
-        greyscale_image.save(output_path)
+        greyscale_image.save(output_path).map_err(Error::Encode)
     }
 
-    pub fn find_char<'b>(&self, charsets: &[&'b str], pixel: u8) -> &'b str {
-        let index = ((charsets.len() - 1) * pixel as usize) / 255 as usize;
-        charsets[index]
+    pub fn find_char<'b>(&self, charset: &'b Charset, pixel: u8) -> &'b str {
+        charset.find(pixel)
     }
 
     
-    pub fn resize(&mut self, image: DynamicImage) -> DynamicImage {
+    pub fn resize(&mut self, image: DynamicImage) -> Result<DynamicImage> {
         let (img_width, img_height) = image.dimensions();
 
-        let (char_aspect_ratio, _char_height, _char_width) = self.get_char_aspect_ratio('W');
+        let (char_aspect_ratio, _char_height, _char_width) = self.get_char_aspect_ratio('W')?;
+        // Wide glyphs take two terminal columns, so the requested width holds half as many cells
+        let cell_columns = self.cell_columns();
+        let char_aspect_ratio = char_aspect_ratio * cell_columns as f32;
 
         // Image aspect ratio
         let img_aspect_ratio = img_width as f32 / img_height as f32;
 
-        // Effective aspect ratio
-        let effective_aspect_ratio = img_aspect_ratio * char_aspect_ratio;
+        // Effective aspect ratio: lines per column of characters
+        let effective_aspect_ratio = char_aspect_ratio / img_aspect_ratio;
 
         // Decide on the number of characters per line and per column
         match (self.options.columns, self.options.lines) {
             (Some(width_chars), Some(height_chars)) => {
-                self.nb_chars_per_line = width_chars;
+                self.nb_chars_per_line = width_chars / cell_columns;
                 self.nb_chars_per_column = height_chars;
             },
             (Some(width_chars), None) => {
-                self.nb_chars_per_line = width_chars;
+                self.nb_chars_per_line = width_chars / cell_columns;
                 self.nb_chars_per_column = (self.nb_chars_per_line as f32 * effective_aspect_ratio).round() as u32;
             },
             (None, Some(height_chars)) => {
@@ -120,29 +341,35 @@ impl <'a>ASCIIImage<'a> {
             },
             (None, None) => {
                 // Default values if neither width nor height is specified
-                self.nb_chars_per_line = 80;
+                self.nb_chars_per_line = 80 / cell_columns;
                 self.nb_chars_per_column = (self.nb_chars_per_line as f32 * effective_aspect_ratio).round() as u32;
             }
         }
 
-        // Resize the image to match the character grid dimensions
-        let resized_image = image.resize_exact(
-            self.nb_chars_per_line,
-            self.nb_chars_per_column,
-            image::imageops::FilterType::Nearest
-        );
+        if self.nb_chars_per_line == 0 || self.nb_chars_per_column == 0 {
+            return Err(Error::InvalidOptions(format!(
+                "the ASCII output would be {}x{} characters",
+                self.nb_chars_per_line, self.nb_chars_per_column
+            )));
+        }
 
-        resized_image
+        // Resize the image to match the character grid dimensions
+        let (cell_width, cell_height) = self.options.mode.cell_size();
+        Ok(self.options.resize_filter.resize(
+            &image,
+            self.nb_chars_per_line * cell_width,
+            self.nb_chars_per_column * cell_height,
+            self.options.luma,
+        ))
     }
 
 
-    fn get_char_aspect_ratio(&self, ch: char) -> (f32, f32, f32) {
+    fn get_char_aspect_ratio(&self, ch: char) -> Result<(f32, f32, f32)> {
         let scale = PxScale::from(self.options.font_size.unwrap_or(12.0));
         
-        let font_data = include_bytes!("fonts/Anonymous Pro.ttf");
-        let font = FontRef::try_from_slice(font_data).expect("Failed to load font");
+        let fonts = self.fonts()?;
         
-        let scaled_font = font.into_scaled(scale.y);
+        let scaled_font = fonts.primary().as_scaled(scale.y);
         
         let glyph_id = scaled_font.glyph_id(ch);
         let char_width = scaled_font.h_advance(glyph_id);
@@ -152,54 +379,99 @@ impl <'a>ASCIIImage<'a> {
         let line_gap = scaled_font.line_gap();
         let char_height = ascent - descent + line_gap;
        
-        (char_width as f32 / char_height as f32, char_height as f32, char_width as f32)
+        Ok((char_width / char_height, char_height, char_width))
+    }
+
+    // Terminal columns per cell: two when the charset has wide glyphs
+    fn cell_columns(&self) -> u32 {
+        match self.options.mode {
+            RenderMode::Charset | RenderMode::Shape if self.options.charsets.is_wide() => 2,
+            _ => 1,
+        }
+    }
+
+    // Geometry of the cells glyphs are measured in, spanning every terminal
+    // column of a cell so that wide glyphs are measured whole
+    fn cell_metrics(&self, fonts: &FontChain) -> CellMetrics {
+        let metrics = self.options.cell_metrics(fonts);
+        CellMetrics { width: metrics.width * self.cell_columns() as f32, ..metrics }
+    }
+
+    fn load_charset(&self) -> Result<Charset> {
+        let mut charset = self.options.charsets.clone();
+        if charset.is_empty() {
+            return Err(Error::InvalidOptions("the charset is empty".to_string()));
+        }
+        if self.options.sort_charset {
+            let mut fonts = self.fonts()?.clone();
+            fonts.cover(charset.glyphs())?;
+            charset.sort_by_density(&fonts, &self.cell_metrics(&fonts), self.options.dedupe_density);
+        }
+        Ok(charset)
     }
 
-    pub fn convert(&mut self) {
-        let image = self.reader();
+    pub fn convert(&mut self) -> Result<AsciiArt> {
+        let image = self.reader()?;
         let greyscale = self.convert_to_greyscale(&image);
-        let ascii_art = self.convert_to_ascii(greyscale);
-        if self.options.output_path != "" {
-            self.save_image(ascii_art, &self.options.output_path);
+        let ascii_art = self.convert_to_ascii(greyscale)?;
+        if self.options.print {
+            self.print(&ascii_art)?;
         }
+        if !self.options.output_path.is_empty() {
+            ascii_art.save_with(&self.options.output_path, &self.options, || self.fonts().cloned())?;
+        }
+        Ok(ascii_art)
+    }
+
+    // Terminal output, with color if enabled; failing to write it is an
+    // output error like failing to save
+    fn print(&self, ascii_art: &AsciiArt) -> Result<()> {
+        let mut stdout = std::io::stdout().lock();
+        let written = if self.options.color {
+            let depth = self.options.color_depth.unwrap_or_else(ColorDepth::detect);
+            stdout.write_all(ascii_art.to_ansi(depth).as_bytes())
+        } else {
+            write!(stdout, "{}", ascii_art)
+        };
+        written.and_then(|_| stdout.flush()).map_err(|err| Error::Encode(image::ImageError::IoError(err)))
     }
 
     // Function to generate animation frames
-    pub fn convert_with_animation(&mut self, total_frames: u32) -> Result<(), image::ImageError> {
-        let image = self.reader();  // Load and resize image
+    pub fn convert_with_animation(&mut self, total_frames: u32) -> Result<()> {
+        let image = self.reader()?;  // Load and resize image
         let greyscale = self.convert_to_greyscale(&image);  // Convert to greyscale
-        let chars = charsets::DEFAULT;  // Load character set for conversion
+        let target = self.convert_to_ascii(greyscale)?;  // Final ASCII art
 
         // Store final positions and corresponding characters
         let mut target_positions = vec![];
-        for y in 0..greyscale.height() {
-            for x in 0..greyscale.width() {
-                let pixel = self.pixels.get_pixel(x, y);
-                let ch = self.find_char(chars, pixel.grey).chars().next().unwrap();
-                target_positions.push(((x, y), ch, Rgba([pixel.r, pixel.g, pixel.b, pixel.a])));
+        for (y, row) in target.rows().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                target_positions.push(((x as u32, y as u32), cell.clone()));
             }
         }
 
         // Create initial random positions for each character
         let mut rng = rand::thread_rng();
-        let mut current_positions: Vec<((f32, f32), char, Rgba<u8>)> = target_positions
+        let mut current_positions: Vec<((f32, f32), ColoredChar)> = target_positions
             .iter()
-            .map(|(_, ch, color)| {
+            .map(|(_, cell)| {
                 (
                     (
                         rng.gen_range(0.0..self.nb_chars_per_line as f32),
                         rng.gen_range(0.0..self.nb_chars_per_column as f32),
                     ),
-                    *ch,
-                    *color,
+                    cell.clone(),
                 )
             })
             .collect();
 
+        // Glyphs are rasterized once and reused by every frame
+        let mut atlas = GlyphAtlas::with_fonts(self.fonts()?.clone());
+
         // Generate each frame of the animation
         for frame_num in 0..total_frames {
             let factor = 1.0 / (total_frames as f32);  // Movement factor per frame
-            let mut ascii_art = vec![vec![ColoredChar { ch: ' ', color: Rgba([255, 255, 255, 0]) }; self.nb_chars_per_line as usize]; self.nb_chars_per_column as usize];
+            let mut ascii_art = AsciiArt::blank(self.nb_chars_per_line, self.nb_chars_per_column).with_cell_columns(target.cell_columns());
 
             // Move each character closer to its target
             for i in 0..current_positions.len() {
@@ -209,19 +481,16 @@ impl <'a>ASCIIImage<'a> {
                 current_positions[i].0 = new_position;
 
                 // Place character in the ASCII art grid if within bounds
-                let x = new_position.0.round() as usize;
-                let y = new_position.1.round() as usize;
-                if x < self.nb_chars_per_line as usize && y < self.nb_chars_per_column as usize {
-                    ascii_art[y][x] = ColoredChar {
-                        ch: current_positions[i].1,
-                        color: current_positions[i].2,
-                    };
+                let x = new_position.0.round() as u32;
+                let y = new_position.1.round() as u32;
+                if let Some(cell) = ascii_art.get_mut(x, y) {
+                    *cell = current_positions[i].1.clone();
                 }
             }
 
             // Save each frame
             let frame_path = format!("output_frame_{:03}.png", frame_num);
-            self.save_image(ascii_art, &frame_path);
+            ascii_art.to_image_with(&self.options, &mut atlas)?.save(&frame_path).map_err(Error::Encode)?;
 
             // Optional sleep to visualize movement in real-time
             sleep(Duration::from_millis(50));
@@ -230,6 +499,69 @@ impl <'a>ASCIIImage<'a> {
         Ok(())
     }
 
+    // Font chain of the options, loaded on first use and shared by every
+    // step of the conversion
+    fn fonts(&self) -> Result<&FontChain> {
+        if let Some(fonts) = self.fonts.get() {
+            return Ok(fonts);
+        }
+        let fonts = self.options.font_chain()?;
+        Ok(self.fonts.get_or_init(|| fonts))
+    }
+
+    // Rasterized charset glyphs, computed on first use
+    fn glyph_shapes(&self) -> Result<&GlyphShapes> {
+        if let Some(shapes) = self.glyph_shapes.get() {
+            return Ok(shapes);
+        }
+        let charset = self.load_charset()?;
+        let mut fonts = self.fonts()?.clone();
+        fonts.cover(charset.glyphs())?;
+        let shapes = GlyphShapes::new(&fonts, &charset, &self.cell_metrics(&fonts));
+        Ok(self.glyph_shapes.get_or_init(|| shapes))
+    }
+
+    // Braille cell (x, y), colored with the average of its lit dots; blank
+    // pixels are never lit
+    fn braille_cell(&self, image: &GrayImage, x: u32, y: u32, threshold: u8) -> ColoredChar {
+        // Dot bits of the Braille pattern, indexed by [row][column] within the cell
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+        let alpha = self.options.alpha;
+        let mut pattern = 0;
+        let mut lit = 0;
+        let mut opaque = 0;
+        let mut sum = [0u32; 4];
+        let mut all = [0u32; 4];
+        for (dy, row) in DOTS.iter().enumerate() {
+            for (dx, bit) in row.iter().enumerate() {
+                let (px, py) = (x * 2 + dx as u32, y * 4 + dy as u32);
+                let pixel = self.pixels.get_pixel(px, py);
+                if alpha.is_blank(pixel.a) {
+                    continue;
+                }
+                let color = alpha.composite(pixel.color());
+                opaque += 1;
+                for (channel, value) in color.0.iter().enumerate() {
+                    all[channel] += *value as u32;
+                }
+                if image.get_pixel(px, py)[0] > threshold {
+                    pattern |= bit;
+                    lit += 1;
+                    for (channel, value) in color.0.iter().enumerate() {
+                        sum[channel] += *value as u32;
+                    }
+                }
+            }
+        }
+
+        if opaque == 0 {
+            return ColoredChar::blank();
+        }
+        let color = if lit > 0 { sum.map(|c| (c / lit) as u8) } else { all.map(|c| (c / opaque) as u8) };
+        ColoredChar::new(char::from_u32(0x2800 + pattern).unwrap(), Rgba(color))
+    }
+
     // Helper function to move a point closer to its target
     fn move_point(&self, current: (f32, f32), target: (f32, f32), factor: f32) -> (f32, f32) {
         let new_x = current.0 + (target.0 - current.0) * factor;
@@ -240,115 +572,294 @@ impl <'a>ASCIIImage<'a> {
 }
 
 impl ASCII for ASCIIImage<'_> {
-    fn reader(&mut self) -> DynamicImage {
-        let image = ImageReader::open(self.image_path.clone()).unwrap().decode().unwrap();
+    fn reader(&mut self) -> Result<DynamicImage> {
+        let image = match &self.source {
+            Source::Path(path) => ImageReader::open(path)?.decode().map_err(Error::Decode)?,
+            Source::Image(image) => image.clone(),
+        };
         self.resize(image)
     }
 
     fn convert_to_greyscale(&mut self, image: &DynamicImage) -> GrayImage {
         let (width, height) = image.dimensions();
-        let mut greyscale_image = GrayImage::new(width, height);
-        for x in 0..width {
-            for y in 0..height {
+        let alpha = self.options.alpha;
+        let mut greyscale_image = GrayImage::from_fn(width, height, |x, y| {
+            image::Luma([self.pixel_to_greyscale(alpha.composite(image.get_pixel(x, y)))])
+        });
+        // Blank pixels keep their hidden colors out of the level statistics
+        self.options.tone().apply(&mut greyscale_image, |x, y| alpha.is_blank(image.get_pixel(x, y)[3]));
+
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
                 let pixel = image.get_pixel(x, y);
-                let greyscale = self.pixel_to_greyscale(pixel);
-                self.pixels.pixels.push(Pixel {
+                pixels.push(Pixel {
                     r: pixel[0],
                     g: pixel[1],
                     b: pixel[2],
                     a: pixel[3],
-                    grey: greyscale,
-                    position: vec![(x, y)]
+                    grey: greyscale_image.get_pixel(x, y)[0],
                 });
-                greyscale_image.put_pixel(x, y, image::Luma([greyscale]));
             }
         }
+        self.pixels = Pixels::new(width, height, pixels);
         greyscale_image
     }
 
 
-    fn convert_to_ascii(&self, image: GrayImage) -> Vec<Vec<ColoredChar>> {
-        let mut charsets: Vec<&str> = Vec::new();
-        let _chars = charsets::from_str(&self.options.charsets, &mut charsets);
-        let mut ascii_art = Vec::new();
-
-        for y in 0..image.height() {
-            let mut line = Vec::new();
-            for x in 0..image.width() {
-                let pixel = self.pixels.get_pixel(x, y);
-                let ch = self.find_char(&charsets, pixel.grey).chars().next().unwrap();
-                let color = Rgba([pixel.r, pixel.g, pixel.b, pixel.a]);
-
-                line.push(ColoredChar { ch, color });
-
-                // Terminal output with color
-                if self.options.color {
-                    let ansi_color = Color::RGB(pixel.r, pixel.g, pixel.b);
-                    if self.options.print {
-                        print!("{}", ansi_color.paint(ch.to_string()));
+    fn convert_to_ascii(&self, mut image: GrayImage) -> Result<AsciiArt> {
+        let (cell_width, cell_height) = self.options.mode.cell_size();
+        let columns = image.width() / cell_width;
+        let lines = image.height() / cell_height;
+        let mut cells = Vec::with_capacity((columns * lines) as usize);
+
+        let alpha = self.options.alpha;
+        let is_blank = |x, y| alpha.is_blank(self.pixels.get_pixel(x, y).a);
+
+        match self.options.mode {
+            RenderMode::Charset => {
+                let charset = self.load_charset()?;
+                let edges = self.options.edges.map(|edges| edges.detect(&image));
+                self.options.dither.apply_masked(&mut image, charset.len(), is_blank);
+                for y in 0..lines {
+                    for x in 0..columns {
+                        let pixel = self.pixels.get_pixel(x, y);
+                        let edge = edges.as_ref().and_then(|edges| edges[(y * columns + x) as usize]);
+                        let color = alpha.composite(pixel.color());
+                        cells.push(match edge {
+                            _ if alpha.is_blank(pixel.a) => ColoredChar::blank(),
+                            Some(ch) => ColoredChar::new(ch, color),
+                            None => ColoredChar::from_glyph(self.find_char(&charset, image.get_pixel(x, y)[0]), color),
+                        });
                     }
+                }
+            },
+            RenderMode::HalfBlock => {
+                for y in 0..lines {
+                    for x in 0..columns {
+                        let top = self.pixels.get_pixel(x, y * 2);
+                        let bottom = self.pixels.get_pixel(x, y * 2 + 1);
+                        let (top_color, bottom_color) = (alpha.composite(top.color()), alpha.composite(bottom.color()));
+                        cells.push(match (alpha.is_blank(top.a), alpha.is_blank(bottom.a)) {
+                            (true, true) => ColoredChar::blank(),
+                            (true, false) => ColoredChar::new('▄', bottom_color),
+                            (false, true) => ColoredChar::new('▀', top_color),
+                            (false, false) => ColoredChar::with_background('▀', top_color, bottom_color),
+                        });
+                    }
+                }
+            },
+            RenderMode::Braille => {
+                let threshold = if self.options.dither == Dither::None {
+                    self.options.threshold
                 } else {
-                    if self.options.print {
-                        print!("{}", ch);
+                    self.options.dither.apply_masked(&mut image, 2, is_blank);
+                    128
+                };
+                for y in 0..lines {
+                    for x in 0..columns {
+                        cells.push(self.braille_cell(&image, x, y, threshold));
                     }
                 }
-            }
-            ascii_art.push(line);
-            if self.options.print {
-                println!();
-            }
+            },
+            RenderMode::Shape => {
+                let shapes = self.glyph_shapes()?;
+                let mut samples = Vec::with_capacity((SHAPE_COLUMNS * SHAPE_ROWS) as usize);
+                for y in 0..lines {
+                    for x in 0..columns {
+                        samples.clear();
+                        let mut sum = [0u32; 4];
+                        let mut opaque = 0;
+                        for dy in 0..SHAPE_ROWS {
+                            for dx in 0..SHAPE_COLUMNS {
+                                let (px, py) = (x * SHAPE_COLUMNS + dx, y * SHAPE_ROWS + dy);
+                                let pixel = self.pixels.get_pixel(px, py);
+                                // Blank pixels count as empty parts of the cell
+                                if alpha.is_blank(pixel.a) {
+                                    samples.push(0.0);
+                                    continue;
+                                }
+                                samples.push(image.get_pixel(px, py)[0] as f32 / 255.0);
+                                opaque += 1;
+                                for (channel, value) in alpha.composite(pixel.color()).0.iter().enumerate() {
+                                    sum[channel] += *value as u32;
+                                }
+                            }
+                        }
+                        // Cells mostly made of blank pixels are blank
+                        if opaque * 2 < SHAPE_COLUMNS * SHAPE_ROWS {
+                            cells.push(ColoredChar::blank());
+                            continue;
+                        }
+                        let glyph = shapes.best_match(&samples, self.options.shape_metric);
+                        cells.push(ColoredChar::from_glyph(glyph, Rgba(sum.map(|c| (c / opaque) as u8))));
+                    }
+                }
+            },
+        }
+        self.options.background.apply(columns, lines, &mut cells);
+        if let Some(palette) = &self.options.palette {
+            palette.apply(self.options.palette_dither, columns, &mut cells);
         }
-        ascii_art
+        Ok(AsciiArt::new(columns, lines, cells).with_cell_columns(self.cell_columns()))
     }
 
-    fn save_image(&self, ascii_art: Vec<Vec<ColoredChar>>, output_path: &str) {
-        let scale = PxScale::from(self.options.font_size.unwrap_or(12.0));
-        let line_height = scale.y.ceil() as u32;
-        let num_lines = ascii_art.len();
-
-        // Load the font and create a scaled version
-        let font_data = include_bytes!("fonts/Anonymous Pro.ttf");
-        let font = FontRef::try_from_slice(font_data).expect("Failed to load font");
-        let glyph = font.clone().glyph_id('W');
-        let scaled_font = font.clone().into_scaled(scale.y);
-
-        // Calculate the width of each line and the maximum width
-        let mut max_line_width = 0.0;
-        let mut line_widths = Vec::new();
-
-        for line in &ascii_art {
-            let line_width: f32 = line.len() as f32 * scaled_font.h_advance(glyph);
-            line_widths.push(line_width);
-            if line_width > max_line_width {
-                max_line_width = line_width;
-            }
-        }
+    fn save_image(&self, ascii_art: &AsciiArt, output_path: &str) -> Result<()> {
+        ascii_art.save_image_with(output_path, &self.options, &mut GlyphAtlas::with_fonts(self.fonts()?.clone()))
+    }
+}
 
-        let width = max_line_width.ceil() as u32;
-        let height = (num_lines as u32) * line_height;
+#[cfg(feature = "python")]
+mod python_bindings;
 
-        let mut img = RgbaImage::new(width, height);
 
-        // Fill the background with white
-        for pixel in img.pixels_mut() {
-            *pixel = Rgba([255 as u8, 255 as u8, 255 as u8, 0 as u8]);
-        }
+// A path in the temp dir unique to this process, so that parallel test runs
+// do not share files
+#[cfg(test)]
+pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("crascii-test-{}-{}", std::process::id(), name))
+}
 
-        // Draw each character with its color
-        for (y_idx, line) in ascii_art.iter().enumerate() {
-            let y = y_idx as f32 * scale.y;
-            let mut x = 0.0;
-            for colored_char in line {
-                let ch_str = colored_char.ch.to_string();
-                draw_text_mut(&mut img, colored_char.color, x as i32, y as i32, scale, &font, &ch_str);
-                x += scaled_font.h_advance(glyph);
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    #[test]
+    fn test_pixels_are_row_major() {
+        let mut image = RgbaImage::new(3, 2);
+        image.put_pixel(2, 0, Rgba([255, 0, 0, 255]));
+        image.put_pixel(0, 1, Rgba([0, 0, 255, 128]));
+
+        let mut ascii_image = ASCIIImage::from_image(DynamicImage::new_rgba8(1, 1), Options::default());
+        ascii_image.convert_to_greyscale(&DynamicImage::ImageRgba8(image));
+
+        let pixels = ascii_image.pixels();
+        assert_eq!((pixels.width(), pixels.height()), (3, 2));
+        assert_eq!(pixels.get_pixel(2, 0).r, 255);
+        assert_eq!(pixels.get_pixel(0, 1).a, 128);
+        assert!(pixels.get(3, 0).is_none());
+        assert_eq!(pixels.rows().count(), 2);
+    }
 
-        let _ = img.save(output_path);
+    #[test]
+    fn test_convert_returns_ascii_art() {
+        let image = RgbaImage::from_fn(4, 2, |x, _| if x < 2 { Rgba([0, 0, 0, 255]) } else { Rgba([255, 255, 255, 255]) });
+        let options = Options { columns: Some(4), lines: Some(2), charsets: Charset::from_glyphs(" #"), ..Options::default() };
+        let mut ascii_image = ASCIIImage::from_image(DynamicImage::ImageRgba8(image), options);
+
+        let art = ascii_image.convert().unwrap();
+        assert_eq!((art.width(), art.height()), (4, 2));
+        assert_eq!(art.get(3, 1).unwrap().ch(), '#');
+        assert_eq!(art.get(0, 0).unwrap().color(), Rgba([0, 0, 0, 255]));
+        assert_eq!(art.to_string(), "  ##\n  ##\n");
     }
-}
 
-#[cfg(feature = "python")]
-mod python_bindings;
+    #[test]
+    fn test_lines_follow_image_aspect_ratio() {
+        let lines = |width, height| {
+            let options = Options { columns: Some(40), ..Options::default() };
+            let mut ascii_image = ASCIIImage::from_image(DynamicImage::new_rgba8(width, height), options);
+            ascii_image.convert().unwrap().height()
+        };
+        // Characters are about twice as tall as wide, so a square image takes
+        // half as many lines as columns
+        let (square, wide, tall) = (lines(100, 100), lines(200, 100), lines(100, 200));
+        assert!((18..=22).contains(&square), "{}", square);
+        assert_eq!((wide, tall), ((square as f32 / 2.0).round() as u32, square * 2));
+    }
+
+    #[test]
+    fn test_write_failures_are_encode_errors() {
+        let art = AsciiArt::new(1, 1, vec![ColoredChar::new('#', Rgba([0, 0, 0, 255]))]);
+        let path = crate::temp_path("missing-dir/art.txt");
+        assert!(matches!(art.save(path.to_str().unwrap(), &Options::default()), Err(Error::Encode(_))));
+    }
+
+    #[test]
+    fn test_inverse_uses_dense_glyphs_for_dark_pixels() {
+        let image = RgbaImage::from_fn(2, 1, |x, _| if x == 0 { Rgba([0, 0, 0, 255]) } else { Rgba([255, 255, 255, 255]) });
+        let options = Options { columns: Some(2), lines: Some(1), charsets: Charset::from_glyphs(" #"), inverse: true, ..Options::default() };
+        let mut ascii_image = ASCIIImage::from_image(DynamicImage::ImageRgba8(image.clone()), options);
+
+        let art = ascii_image.convert().unwrap();
+        assert_eq!(art.to_string(), "# \n");
+        assert_eq!(ascii_image.options.paper(), Some(Rgba([255, 255, 255, 255])));
 
+        // Already inverted levels are not swapped back
+        let options = Options { tone: Tone { invert: true, ..Tone::default() }, ..ascii_image.options };
+        let art = ASCIIImage::from_image(DynamicImage::ImageRgba8(image), options).convert().unwrap();
+        assert_eq!(art.to_string(), "# \n");
+    }
+
+    #[test]
+    fn test_wide_charset_halves_columns() {
+        let image = RgbaImage::from_fn(4, 1, |x, _| if x < 2 { Rgba([0, 0, 0, 255]) } else { Rgba([255, 255, 255, 255]) });
+        let options = Options { columns: Some(4), lines: Some(1), charsets: Charset::from_glyphs("#龍"), ..Options::default() };
+        let mut ascii_image = ASCIIImage::from_image(DynamicImage::ImageRgba8(image), options);
+
+        let art = ascii_image.convert().unwrap();
+        assert_eq!((art.width(), art.cell_columns()), (2, 2));
+        assert_eq!(art.to_string(), "# 龍\n");
+
+        // Glyphs are measured over both columns
+        let fonts = ascii_image.options.font_chain().unwrap();
+        assert_eq!(ascii_image.cell_metrics(&fonts).width, ascii_image.options.cell_metrics(&fonts).width * 2.0);
+    }
+
+    #[test]
+    fn test_half_block_uses_two_pixels_per_cell() {
+        let image = RgbaImage::from_fn(2, 2, |_, y| if y == 0 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) });
+        let options = Options { columns: Some(2), lines: Some(1), mode: RenderMode::HalfBlock, ..Options::default() };
+        let mut ascii_image = ASCIIImage::from_image(DynamicImage::ImageRgba8(image), options);
+
+        let art = ascii_image.convert().unwrap();
+        assert_eq!((art.width(), art.height()), (2, 1));
+        let cell = art.get(0, 0).unwrap();
+        assert_eq!(cell.ch(), '▀');
+        assert_eq!(cell.color(), Rgba([255, 0, 0, 255]));
+        assert_eq!(cell.background(), Some(Rgba([0, 0, 255, 255])));
+    }
+
+    #[test]
+    fn test_transparent_pixels_are_blank_or_matted() {
+        let image = RgbaImage::from_fn(2, 2, |x, y| if x == 0 && y == 0 { Rgba([0, 0, 0, 0]) } else { Rgba([255, 255, 255, 255]) });
+        let convert = |options: Options<'static>| ASCIIImage::from_image(DynamicImage::ImageRgba8(image.clone()), options).convert().unwrap();
+        let charset = Charset::from_glyphs(".#");
+
+        let art = convert(Options { columns: Some(2), lines: Some(2), charsets: charset.clone(), ..Options::default() });
+        assert_eq!(art.to_string(), " #\n##\n");
+        let art = convert(Options { columns: Some(2), lines: Some(2), charsets: charset, alpha: Alpha::Matte(Rgba([255, 255, 255, 255])), ..Options::default() });
+        assert_eq!(art.to_string(), "##\n##\n");
+
+        let art = convert(Options { columns: Some(2), lines: Some(1), mode: RenderMode::HalfBlock, ..Options::default() });
+        assert_eq!(art.get(0, 0), Some(&ColoredChar::new('▄', Rgba([255, 255, 255, 255]))));
+        assert_eq!(art.get(1, 0).unwrap().background(), Some(Rgba([255, 255, 255, 255])));
+    }
+
+    #[test]
+    fn test_auto_levels_ignore_transparent_pixels() {
+        // A grey ramp after a transparent black area
+        let image = RgbaImage::from_fn(8, 1, |x, _| match x {
+            0..4 => Rgba([0, 0, 0, 0]),
+            _ => Rgba([60 + x as u8 * 20, 60 + x as u8 * 20, 60 + x as u8 * 20, 255]),
+        });
+        let tone = Tone { levels: Levels::Auto, ..Tone::default() };
+        let options = Options { columns: Some(8), lines: Some(1), charsets: Charset::from_glyphs("0123456789"), tone, ..Options::default() };
+        let art = ASCIIImage::from_image(DynamicImage::ImageRgba8(image), options).convert().unwrap();
+        assert_eq!(art.to_string(), "    0369\n");
+    }
+
+    #[test]
+    fn test_braille_lights_bright_dots() {
+        // Left column bright, right column dark
+        let image = RgbaImage::from_fn(2, 4, |x, _| if x == 0 { Rgba([255, 255, 255, 255]) } else { Rgba([0, 0, 0, 255]) });
+        let options = Options { columns: Some(1), lines: Some(1), mode: RenderMode::Braille, ..Options::default() };
+        let mut ascii_image = ASCIIImage::from_image(DynamicImage::ImageRgba8(image), options);
+
+        let art = ascii_image.convert().unwrap();
+        let cell = art.get(0, 0).unwrap();
+        assert_eq!(cell.ch(), '\u{2847}');
+        assert_eq!(cell.color(), Rgba([255, 255, 255, 255]));
+    }
+}
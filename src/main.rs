@@ -1,13 +1,63 @@
 use clap::Parser;
 use std::borrow::Cow; 
-use crascii::{ASCIIImage, Options};
+use std::io::Read;
+use std::path::PathBuf;
+use std::str::FromStr;
+use crascii::{ASCIIImage, Alpha, Background, Charset, ColorDepth, Dither, EdgeDetector, EdgeGlyphs, Edges, Error, FontSource, Levels, Luma, Options, Palette, OutputFormat, RenderMode, ResizeFilter, ShapeMetric, Tone};
+
+// Process exit codes, one per failure class. Clap itself exits with 2 on
+// malformed command lines.
+const EXIT_INVALID_ARGS: i32 = 1;
+const EXIT_IO: i32 = 3;
+const EXIT_DECODE: i32 = 4;
+const EXIT_ENCODE: i32 = 5;
+const EXIT_FONT: i32 = 6;
+const EXIT_INVALID_OPTIONS: i32 = 7;
+
+// A charset, or a charset file left for `run` to load so that its errors get
+// the exit codes of the other file errors rather than clap's
+#[derive(Clone, Debug, PartialEq)]
+enum CharsetArg {
+    Charset(Charset),
+    File(PathBuf),
+}
+
+impl FromStr for CharsetArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('@') {
+            Some(path) => Ok(CharsetArg::File(PathBuf::from(path))),
+            None => s.parse().map(CharsetArg::Charset),
+        }
+    }
+}
+
+// A builtin palette, or a palette file left for `run` to load like charset
+// files
+#[derive(Clone, Debug, PartialEq)]
+enum PaletteArg {
+    Named(Palette),
+    File(PathBuf),
+}
+
+impl FromStr for PaletteArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('@') {
+            Some(path) => Ok(PaletteArg::File(PathBuf::from(path))),
+            None => s.parse().map(PaletteArg::Named),
+        }
+    }
+}
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// The image to convert
-    #[arg(short, long, help = "Path to the input image file to be converted into ASCII art")]
-    image: String,
+    #[arg(short, long, required_unless_present = "list_charsets", help = "Path to the input image file to be converted into ASCII art, or - to read it from stdin")]
+    image: Option<String>,
 
     /// The width of the output image
     #[arg(short = 'w', long, help = "Number of columns (width) for the ASCII output")]
@@ -22,9 +72,25 @@ struct Args {
     #[arg(short = 'C', long, help = "Enable colored ASCII output")]
     color: bool,
 
+    /// Color depth
+    #[arg(long, help = "Colors used by --color when printing: truecolor, 256, 16 or mono (default: detected from COLORTERM, TERM and NO_COLOR, plain text when not a terminal)")]
+    color_depth: Option<ColorDepth>,
+
     /// The charsets to use
-    #[arg(short, long, default_value = "default", help = "Character set to use for ASCII conversion")]
-    charsets: String,
+    #[arg(short, long, default_value = "default", help = "Charset name (see --list-charsets), @file to load a charset file, or the glyphs to use, darkest first, prefixed with glyphs: when they form a lowercase word")]
+    charsets: CharsetArg,
+
+    /// List the charsets
+    #[arg(long, help = "List the available charsets with a preview ramp and exit")]
+    list_charsets: bool,
+
+    /// Sort the charset
+    #[arg(long, help = "Sort the charset from lightest to darkest by measured glyph density")]
+    sort_charset: bool,
+
+    /// Drop near-duplicate densities
+    #[arg(long, requires = "sort_charset", help = "With --sort-charset, drop glyphs whose density is within this fraction of the previous one, e.g. 0.01")]
+    dedupe_density: Option<f32>,
 
     /// Output file
     #[arg(short, long, help = "Path to save the ASCII output")]
@@ -37,13 +103,137 @@ struct Args {
     /// Font size
     #[arg(short, long, help = "Font size for the ASCII output")]
     font_size: Option<f32>,
+
+    /// Output format
+    #[arg(long, help = "Output format: text, ansi, html, svg or image (default: guessed from the output path extension)")]
+    format: Option<OutputFormat>,
+
+    /// Font family of the HTML output
+    #[arg(long, default_value = "monospace", help = "CSS font family for HTML and SVG output")]
+    font_family: String,
+
+    /// Palette
+    #[arg(long, help = "Limit colors to a palette: gameboy, cga, solarized, nord, grayscale-N, or @file for a GIMP .gpl or hex color list")]
+    palette: Option<PaletteArg>,
+
+    /// Palette dithering
+    #[arg(long, default_value = "none", help = "Dithering of the colors mapped to --palette: none, floyd-steinberg, atkinson, jarvis-judice-ninke, bayer2, bayer4 or bayer8")]
+    palette_dither: Dither,
+
+    /// Background
+    #[arg(long, default_value = "transparent", help = "Background of image, HTML and SVG output: transparent, a color such as #000000, or image for the blurred and darkened image colors")]
+    background: Background,
+
+    /// Background blur
+    #[arg(long, default_value_t = 1.0, help = "With --background image, blur radius in cells")]
+    background_blur: f32,
+
+    /// Background darkening
+    #[arg(long, default_value_t = 0.5, help = "With --background image, fraction by which the colors are darkened")]
+    background_darken: f32,
+
+    /// Inverse
+    #[arg(long, help = "Draw dark glyphs on light paper: dense glyphs for dark pixels, on white unless --background is a color")]
+    inverse: bool,
+
+    /// Embed the font in SVG output
+    #[arg(long, help = "Embed the font in SVG output")]
+    embed_font: bool,
+
+    /// Font
+    #[arg(long, help = "Font for image and SVG output and for the cell aspect ratio: anonymous-pro, dejavu-sans, hack-nerd-font, hack-nerd-font-mono or a TTF/OTF file path")]
+    font: Option<FontSource>,
+
+    /// Fallback fonts
+    #[arg(long = "fallback-font", value_name = "PATH", help = "Font file searched for glyphs missing from the bundled font in image output; may be repeated")]
+    fallback_fonts: Vec<PathBuf>,
+
+    /// Render mode
+    #[arg(short, long, default_value = "charset", help = "Render mode: charset, half-block, braille or shape")]
+    mode: RenderMode,
+
+    /// Resize filter
+    #[arg(long, default_value = "area", help = "Filter scaling the image to the character grid: nearest, triangle, catmull-rom, lanczos3 or area (mean of the covered pixels)")]
+    resize_filter: ResizeFilter,
+
+    /// Shape metric
+    #[arg(long, default_value = "ssd", help = "Glyph similarity measure in shape mode: ssd or ssim")]
+    shape_metric: ShapeMetric,
+
+    /// Braille threshold
+    #[arg(long, default_value_t = 128, help = "Brightness above which a Braille dot is lit")]
+    threshold: u8,
+
+    /// Alpha policy
+    #[arg(long, default_value = "threshold", help = "Transparent pixels: threshold (blank cells below --alpha-threshold) or a matte color to composite over, such as #ffffff")]
+    alpha: Alpha,
+
+    /// Alpha threshold
+    #[arg(long, default_value_t = 128, help = "Opacity below which a pixel is left blank with --alpha threshold")]
+    alpha_threshold: u8,
+
+    /// Luma
+    #[arg(long, default_value = "rec709", help = "Grey level formula: rec601, rec709, linear (linear-light luminance) or lightness (CIE L*)")]
+    luma: Luma,
+
+    /// Brightness
+    #[arg(long, default_value_t = 0.0, allow_hyphen_values = true, help = "Offset added to the grey levels, from -1 to 1")]
+    brightness: f32,
+
+    /// Contrast
+    #[arg(long, default_value_t = 1.0, help = "Factor scaling the grey levels around mid-grey")]
+    contrast: f32,
+
+    /// Gamma
+    #[arg(long, default_value_t = 1.0, help = "Gamma of the grey levels; above 1 brightens the mid-tones")]
+    gamma: f32,
+
+    /// Invert
+    #[arg(long, conflicts_with = "inverse", help = "Swap dark and bright grey levels, keeping the background; --inverse also does this")]
+    invert: bool,
+
+    /// Levels
+    #[arg(long, default_value = "none", help = "Spread the grey levels: none, auto (stretch to black and white), equalize or clahe")]
+    levels: Levels,
+
+    /// CLAHE tiles
+    #[arg(long, default_value_t = 8, help = "Tiles across each dimension with --levels clahe")]
+    clahe_tiles: u32,
+
+    /// CLAHE clip limit
+    #[arg(long, default_value_t = 2.0, help = "Histogram clip limit, relative to the mean bin count, with --levels clahe")]
+    clahe_clip_limit: f32,
+
+    /// Dithering
+    #[arg(long, default_value = "none", help = "Dithering before glyph lookup: none, floyd-steinberg, atkinson, jarvis-judice-ninke, bayer2, bayer4 or bayer8")]
+    dither: Dither,
+
+    /// Edge detection
+    #[arg(long, help = "Draw directional glyphs along edges found with sobel or canny")]
+    edges: Option<EdgeDetector>,
+
+    /// Edge threshold
+    #[arg(long, default_value_t = 200.0, help = "Gradient magnitude above which a pixel is an edge (canny high threshold)")]
+    edge_threshold: f32,
+
+    /// Canny low threshold
+    #[arg(long, help = "Canny low threshold (default: half the edge threshold)")]
+    edge_low_threshold: Option<f32>,
+
+    /// Edge glyphs
+    #[arg(long, default_value = "|/-\\_", help = "Edge glyphs: vertical, diagonal up, horizontal, diagonal down, low horizontal")]
+    edge_glyphs: EdgeGlyphs,
 }
 
 
 fn parse_args() -> Result<Args, String> {
     let args = Args::parse();
 
-    if args.image.is_empty() {
+    if args.list_charsets {
+        return Ok(args);
+    }
+
+    if args.image.as_deref().unwrap_or("").is_empty() {
         return Err("Image path cannot be empty".to_string());
     }
 
@@ -54,32 +244,127 @@ fn parse_args() -> Result<Args, String> {
     Ok(args)
 }
 
-fn main() -> Result<(), image::ImageError> {
+fn exit_code(err: &Error) -> i32 {
+    match err {
+        Error::Io(_) => EXIT_IO,
+        Error::Decode(_) => EXIT_DECODE,
+        Error::Encode(_) => EXIT_ENCODE,
+        Error::Font(_) | Error::FontFile(..) | Error::MissingGlyphs(_) => EXIT_FONT,
+        Error::InvalidOptions(_) => EXIT_INVALID_OPTIONS,
+    }
+}
+
+// Width, in glyphs, of the ramp shown by --list-charsets
+const PREVIEW_WIDTH: usize = 32;
+
+fn list_charsets() {
+    let names = Charset::names();
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+    for name in names {
+        if let Some(charset) = Charset::named(&name) {
+            println!("{:<width$}  {:>3} glyphs  {}", name, charset.len(), charset.preview(PREVIEW_WIDTH), width = width);
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), Error> {
+    if args.list_charsets {
+        list_charsets();
+        return Ok(());
+    }
+
+    let background = match args.background {
+        Background::Image { .. } => Background::Image { blur: args.background_blur, darken: args.background_darken },
+        background => background,
+    };
+    let alpha = match args.alpha {
+        Alpha::Threshold(_) => Alpha::Threshold(args.alpha_threshold),
+        alpha => alpha,
+    };
+    let edges = args.edges.map(|detector| {
+        let detector = match detector {
+            EdgeDetector::Sobel { .. } => EdgeDetector::Sobel { threshold: args.edge_threshold },
+            EdgeDetector::Canny { .. } => EdgeDetector::Canny {
+                low: args.edge_low_threshold.unwrap_or(args.edge_threshold / 2.0),
+                high: args.edge_threshold,
+            },
+        };
+        Edges { detector, glyphs: args.edge_glyphs }
+    });
+    let levels = match args.levels {
+        Levels::Clahe { .. } => Levels::Clahe { tiles: args.clahe_tiles, clip_limit: args.clahe_clip_limit },
+        levels => levels,
+    };
+    let charsets = match args.charsets {
+        CharsetArg::Charset(charset) => charset,
+        CharsetArg::File(path) => Charset::load(path)?,
+    };
+    let palette = match args.palette {
+        Some(PaletteArg::Named(palette)) => Some(palette),
+        Some(PaletteArg::File(path)) => Some(Palette::load(path)?),
+        None => None,
+    };
+    let options = Options {
+        columns: args.columns,
+        lines: args.lines,
+        color: args.color,
+        color_depth: args.color_depth,
+        print: args.print,
+        charsets,
+        output_path: Cow::Owned(args.output_path.unwrap_or("".to_string())),
+        font_size: args.font_size,
+        format: args.format,
+        font_family: Cow::Owned(args.font_family),
+        palette,
+        palette_dither: args.palette_dither,
+        background,
+        inverse: args.inverse,
+        embed_font: args.embed_font,
+        font: args.font,
+        fallback_fonts: args.fallback_fonts.into_iter().map(FontSource::Path).collect(),
+        mode: args.mode,
+        resize_filter: args.resize_filter,
+        threshold: args.threshold,
+        alpha,
+        luma: args.luma,
+        tone: Tone { brightness: args.brightness, contrast: args.contrast, gamma: args.gamma, invert: args.invert, levels },
+        dither: args.dither,
+        edges,
+        shape_metric: args.shape_metric,
+        sort_charset: args.sort_charset,
+        dedupe_density: args.dedupe_density,
+    };
+
+    let image = args.image.unwrap_or_default();
+    let mut ascii_image = if image == "-" {
+        let mut bytes = Vec::new();
+        std::io::stdin().read_to_end(&mut bytes)?;
+        ASCIIImage::from_bytes(&bytes, options)?
+    } else {
+        ASCIIImage::new(image, options)
+    };
+    ascii_image.convert()?;
+    Ok(())
+}
+
+fn main() {
     match parse_args() {
         Ok(args) => {
-            let mut ascii_image = ASCIIImage::new(args.image, Options {
-                columns: args.columns,
-                lines: args.lines,
-                color: args.color,
-                print: args.print,
-                charsets: Cow::Owned(args.charsets),
-                output_path: Cow::Owned(args.output_path.unwrap_or("".to_string())),
-                font_size: args.font_size,
-            });
-            ascii_image.convert();
+            if let Err(err) = run(args) {
+                eprintln!("Error: {}", err);
+                std::process::exit(exit_code(&err));
+            }
         },
         Err(err) => {
             eprintln!("Error: {}", err);
-            std::process::exit(1);
+            std::process::exit(EXIT_INVALID_ARGS);
         }
     }
-
-    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Args;
+    use super::{Args, Charset, CharsetArg, OutputFormat, Palette, PaletteArg};
     use clap::Parser;
 
     #[test]
@@ -93,11 +378,45 @@ mod tests {
             "--lines", "40",
         ];
         let args = Args::parse_from(args);
-        assert_eq!(args.image, "input.png");
+        assert_eq!(args.image.as_deref(), Some("input.png"));
         assert_eq!(args.output_path, Some("output.txt".to_string()));
         assert!(args.color);
         assert_eq!(args.columns, Some(80));
         assert_eq!(args.lines, Some(40));
+        assert_eq!(args.format, None);
+    }
+
+    #[test]
+    fn test_parse_invert() {
+        assert!(Args::parse_from(["crascii", "-i", "input.png", "-p", "--invert"]).invert);
+        assert!(Args::try_parse_from(["crascii", "-i", "input.png", "-p", "--invert", "--inverse"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_format() {
+        let args = Args::parse_from(["crascii", "-i", "input.png", "-o", "out.png", "--format", "ansi"]);
+        assert_eq!(args.format, Some(OutputFormat::Ansi));
+        assert!(Args::try_parse_from(["crascii", "-i", "input.png", "--format", "gif"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_charsets() {
+        let args = Args::parse_from(["crascii", "-i", "input.png", "-p", "-c", "block"]);
+        assert_eq!(args.charsets, CharsetArg::Charset(Charset::named("block").unwrap()));
+        assert!(Args::try_parse_from(["crascii", "-i", "input.png", "-c", "blocks"]).is_err());
+        assert_eq!(Args::parse_from(["crascii", "-i", "input.png", "-c", "glyphs:abc"]).charsets, CharsetArg::Charset(Charset::from_glyphs("abc")));
+        // Charset files are only read by `run`
+        assert_eq!(Args::parse_from(["crascii", "-i", "input.png", "-c", "@missing.toml"]).charsets, CharsetArg::File("missing.toml".into()));
+        assert!(Args::parse_from(["crascii", "--list-charsets"]).image.is_none());
     }
-}
 
+    #[test]
+    fn test_parse_palette() {
+        let args = Args::parse_from(["crascii", "-i", "input.png", "-p", "--palette", "cga"]);
+        assert_eq!(args.palette, Some(PaletteArg::Named(Palette::named("cga").unwrap())));
+        // Palette files are only read by `run`
+        let args = Args::parse_from(["crascii", "-i", "input.png", "-p", "--palette", "@missing.gpl"]);
+        assert_eq!(args.palette, Some(PaletteArg::File("missing.gpl".into())));
+        assert!(Args::try_parse_from(["crascii", "-i", "input.png", "-p", "--palette", "vga"]).is_err());
+    }
+}
@@ -0,0 +1,46 @@
+use std::str::FromStr;
+
+use crate::shapes::{SHAPE_COLUMNS, SHAPE_ROWS};
+
+/// How the cells of the ASCII art are derived from the image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderMode {
+    /// One charset glyph per pixel, picked from its brightness.
+    #[default]
+    Charset,
+    /// `▀` with the upper pixel as foreground color and the lower one as
+    /// background color, doubling the vertical resolution.
+    HalfBlock,
+    /// Braille patterns (U+2800) with one dot per pixel of a 2x4 block, lit
+    /// when the pixel is brighter than `Options::threshold`.
+    Braille,
+    /// The charset glyph whose rasterized shape best matches a 4x8 sampling
+    /// of the cell, compared with `Options::shape_metric`.
+    Shape,
+}
+
+impl RenderMode {
+    /// Number of source pixels sampled for each cell, horizontally and vertically.
+    pub fn cell_size(self) -> (u32, u32) {
+        match self {
+            RenderMode::Charset => (1, 1),
+            RenderMode::HalfBlock => (1, 2),
+            RenderMode::Braille => (2, 4),
+            RenderMode::Shape => (SHAPE_COLUMNS, SHAPE_ROWS),
+        }
+    }
+}
+
+impl FromStr for RenderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "charset" => Ok(RenderMode::Charset),
+            "half-block" | "halfblock" => Ok(RenderMode::HalfBlock),
+            "braille" => Ok(RenderMode::Braille),
+            "shape" => Ok(RenderMode::Shape),
+            _ => Err(format!("unknown render mode '{}' (expected charset, half-block, braille or shape)", s)),
+        }
+    }
+}
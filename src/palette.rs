@@ -0,0 +1,172 @@
+use image::Rgba;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::color::lab;
+use crate::{parse_color, ColoredChar, Dither, Error, Result};
+
+const GAMEBOY: &[&str] = &["#0f380f", "#306230", "#8bac0f", "#9bbc0f"];
+const CGA: &[&str] = &[
+    "#000000", "#0000aa", "#00aa00", "#00aaaa", "#aa0000", "#aa00aa", "#aa5500", "#aaaaaa",
+    "#555555", "#5555ff", "#55ff55", "#55ffff", "#ff5555", "#ff55ff", "#ffff55", "#ffffff",
+];
+const SOLARIZED: &[&str] = &[
+    "#002b36", "#073642", "#586e75", "#657b83", "#839496", "#93a1a1", "#eee8d5", "#fdf6e3",
+    "#b58900", "#cb4b16", "#dc322f", "#d33682", "#6c71c4", "#268bd2", "#2aa198", "#859900",
+];
+const NORD: &[&str] = &[
+    "#2e3440", "#3b4252", "#434c5e", "#4c566a", "#d8dee9", "#e5e9f0", "#eceff4", "#8fbcbb",
+    "#88c0d0", "#81a1c1", "#5e81ac", "#bf616a", "#d08770", "#ebcb8b", "#a3be8c", "#b48ead",
+];
+
+/// A fixed set of colors every cell color is mapped to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    colors: Vec<Rgba<u8>>,
+    // CIELAB coordinates of `colors`, for perceptual matching
+    labs: Vec<[f32; 3]>,
+}
+
+impl Palette {
+    /// Panics if `colors` is empty.
+    pub fn new(colors: Vec<Rgba<u8>>) -> Palette {
+        assert!(!colors.is_empty(), "a palette needs at least one color");
+        let labs = colors.iter().map(|c| lab([c[0], c[1], c[2]])).collect();
+        Palette { colors, labs }
+    }
+
+    /// `levels` evenly spaced greys from black to white.
+    pub fn grayscale(levels: usize) -> Palette {
+        let levels = levels.clamp(2, 256);
+        Palette::new((0..levels).map(|i| (i * 255 / (levels - 1)) as u8).map(|v| Rgba([v, v, v, 255])).collect())
+    }
+
+    /// A builtin palette: `gameboy`, `cga`, `solarized`, `nord` or
+    /// `grayscale-N` with N levels.
+    pub fn named(name: &str) -> Option<Palette> {
+        let name = name.to_ascii_lowercase();
+        let hex = match name.as_str() {
+            "gameboy" => GAMEBOY,
+            "cga" => CGA,
+            "solarized" => SOLARIZED,
+            "nord" => NORD,
+            _ => {
+                let levels = name.strip_prefix("grayscale-").or_else(|| name.strip_prefix("greyscale-"))?;
+                return levels.parse().ok().filter(|levels| (2..=256).contains(levels)).map(Palette::grayscale);
+            },
+        };
+        Some(Palette::new(hex.iter().map(|hex| parse_color(hex).unwrap()).collect()))
+    }
+
+    /// Reads a GIMP `.gpl` palette, or a list of hex colors separated by
+    /// whitespace or newlines.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Palette> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let invalid = |err: String| Error::InvalidOptions(format!("invalid palette file '{}': {}", path.display(), err));
+
+        let colors = if text.starts_with("GIMP Palette") {
+            parse_gpl(&text).map_err(invalid)?
+        } else {
+            text.split_whitespace().map(parse_color).collect::<std::result::Result<Vec<_>, _>>().map_err(invalid)?
+        };
+        if colors.is_empty() {
+            return Err(invalid("the palette is empty".to_string()));
+        }
+        Ok(Palette::new(colors))
+    }
+
+    pub fn colors(&self) -> &[Rgba<u8>] {
+        &self.colors
+    }
+
+    /// The palette color perceptually closest to `color`, keeping its alpha.
+    pub fn nearest(&self, color: Rgba<u8>) -> Rgba<u8> {
+        let target = lab([color[0], color[1], color[2]]);
+        let distance = |lab: &[f32; 3]| lab.iter().zip(&target).map(|(a, b)| (a - b) * (a - b)).sum::<f32>();
+        let index = (0..self.labs.len()).min_by(|&a, &b| distance(&self.labs[a]).total_cmp(&distance(&self.labs[b]))).unwrap_or(0);
+        let [r, g, b, _] = self.colors[index].0;
+        Rgba([r, g, b, color[3]])
+    }
+
+    // Maps the colors of a `columns`-wide grid of cells, dithering the
+    // foregrounds; backgrounds go to their nearest color
+    pub(crate) fn apply(&self, dither: Dither, columns: u32, cells: &mut [ColoredChar]) {
+        // Typical distance between colors on one channel, as if spread on a cube
+        let spread = 255.0 / (self.colors.len() as f32).cbrt().max(1.0);
+        let mut colors: Vec<Rgba<u8>> = cells.iter().map(|cell| cell.color()).collect();
+        dither.apply_colors(&mut colors, columns as usize, spread, |color| self.nearest(color));
+        for (cell, color) in cells.iter_mut().zip(colors) {
+            cell.set_color(color);
+            cell.set_background(cell.background().map(|background| self.nearest(background)));
+        }
+    }
+}
+
+// Colors of the "R G B name" lines of a GIMP palette
+fn parse_gpl(text: &str) -> std::result::Result<Vec<Rgba<u8>>, String> {
+    let mut colors = Vec::new();
+    for line in text.lines().skip(1) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.contains(':') {
+            continue;
+        }
+        let channels: std::result::Result<Vec<u8>, _> = line.split_whitespace().take(3).map(str::parse).collect();
+        match channels.as_deref() {
+            Ok([r, g, b]) => colors.push(Rgba([*r, *g, *b, 255])),
+            _ => return Err(format!("invalid color line '{}'", line)),
+        }
+    }
+    Ok(colors)
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// A builtin palette name, or `@path` to load a palette file.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix('@') {
+            return Palette::load(path).map_err(|err| err.to_string());
+        }
+        Palette::named(s).ok_or_else(|| format!("unknown palette '{}' (expected gameboy, cga, solarized, nord, grayscale-N or @file)", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest_color() {
+        let gameboy: Palette = "gameboy".parse().unwrap();
+        assert_eq!(gameboy.nearest(Rgba([0, 0, 0, 128])), Rgba([0x0f, 0x38, 0x0f, 128]));
+        assert_eq!(gameboy.nearest(Rgba([150, 190, 40, 255])), Rgba([0x9b, 0xbc, 0x0f, 255]));
+        assert_eq!("grayscale-3".parse::<Palette>().unwrap().nearest(Rgba([140, 120, 130, 255])), Rgba([127, 127, 127, 255]));
+        assert!("grayscale-1".parse::<Palette>().is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let path = crate::temp_path("palette.gpl");
+        std::fs::write(&path, "GIMP Palette\nName: Test\nColumns: 2\n# comment\n255   0   0\tRed\n  0 0 255 Blue\n").unwrap();
+        let gpl = Palette::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(gpl.unwrap().colors(), &[Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])]);
+
+        let path = crate::temp_path("palette.hex");
+        std::fs::write(&path, "#000000 ffffff\n#f00\n").unwrap();
+        let hex = Palette::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(hex.unwrap().colors().len(), 3);
+        assert!(matches!(Palette::load(&path), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn test_dithering_mixes_palette_colors() {
+        let palette = Palette::grayscale(2);
+        let mut cells = vec![ColoredChar::new('#', Rgba([128, 128, 128, 255])); 64];
+        palette.apply(Dither::FloydSteinberg, 8, &mut cells);
+        let white = cells.iter().filter(|cell| cell.color() == Rgba([255, 255, 255, 255])).count();
+        assert!((24..=40).contains(&white), "{} white cells", white);
+    }
+}
@@ -44,16 +44,19 @@ mod python_bindings {
 
     // Implement a method to convert PyOptions to Options<'static>
     impl PyOptions {
-        fn to_options(&self) -> Options<'static> {
+        fn to_options(&self) -> PyResult<Options<'static>> {
+            let charsets = self.charsets.parse().map_err(PyErr::new::<pyo3::exceptions::PyValueError, _>)?;
 
-            Options {
+            Ok(Options {
                 columns: self.columns,
                 lines: self.lines,
                 color: self.color,
-                charsets: Cow::Owned(self.charsets.clone()),
+                print: false,
+                charsets,
                 output_path: Cow::Owned(self.output_path.clone()),
-
-            }
+                font_size: None,
+                ..Options::default()
+            })
         }
     }
 
@@ -68,18 +71,18 @@ mod python_bindings {
     #[pymethods]
     impl PyASCIIImage {
         #[new]
-        pub fn new(image_path: String, options: PyOptions) -> Self {
+        pub fn new(image_path: String, options: PyOptions) -> PyResult<Self> {
            
-            let ascii_image = ASCIIImage::new(image_path.clone(), options.to_options());
+            let ascii_image = ASCIIImage::new(image_path.clone(), options.to_options()?);
 
-            PyASCIIImage {
+            Ok(PyASCIIImage {
                 ascii_image,
                 _image_path: image_path,
-            }
+            })
         }
 
-        pub fn convert(&mut self) -> PyResult<()> {
-            self.ascii_image.convert().map_err(|e| {
+        pub fn convert(&mut self) -> PyResult<String> {
+            self.ascii_image.convert().map(|art| art.to_string()).map_err(|e| {
                 PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("{}", e))
             })
         }
@@ -0,0 +1,132 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::str::FromStr;
+
+use crate::color::{encode, linear};
+use crate::Luma;
+
+/// How the image is scaled down to the character grid.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResizeFilter {
+    /// One source pixel per sample, aliased on fine textures.
+    Nearest,
+    /// Bilinear interpolation.
+    Triangle,
+    /// Bicubic interpolation.
+    CatmullRom,
+    Lanczos3,
+    /// The mean of all the source pixels covered by each sample, weighted by
+    /// their opacity. Colors are averaged in linear light for the `Linear`
+    /// and `Lightness` lumas, so that grey levels follow the mean luminance.
+    #[default]
+    Area,
+}
+
+impl ResizeFilter {
+    pub(crate) fn resize(self, image: &DynamicImage, width: u32, height: u32, luma: Luma) -> DynamicImage {
+        let filter = match self {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+            ResizeFilter::Area => {
+                let linear = matches!(luma, Luma::Linear | Luma::Lightness);
+                return DynamicImage::ImageRgba8(area_average(&image.to_rgba8(), width, height, linear));
+            },
+        };
+        image.resize_exact(width, height, filter)
+    }
+}
+
+impl FromStr for ResizeFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nearest" => Ok(ResizeFilter::Nearest),
+            "triangle" | "bilinear" => Ok(ResizeFilter::Triangle),
+            "catmull-rom" | "catmullrom" | "bicubic" => Ok(ResizeFilter::CatmullRom),
+            "lanczos3" | "lanczos" => Ok(ResizeFilter::Lanczos3),
+            "area" | "box" => Ok(ResizeFilter::Area),
+            _ => Err(format!("unknown resize filter '{}' (expected nearest, triangle, catmull-rom, lanczos3 or area)", s)),
+        }
+    }
+}
+
+// Source pixels overlapping each of `size` output pixels spread over
+// `source` input pixels, with the length of their overlap
+fn spans(source: u32, size: u32) -> Vec<Vec<(u32, f32)>> {
+    let scale = source as f32 / size as f32;
+    (0..size)
+        .map(|i| {
+            let (start, end) = (i as f32 * scale, ((i + 1) as f32 * scale).min(source as f32));
+            (start.floor() as u32..(end.ceil() as u32).min(source))
+                .map(|p| (p, end.min(p as f32 + 1.0) - start.max(p as f32)))
+                .filter(|&(_, weight)| weight > 0.0)
+                .collect()
+        })
+        .collect()
+}
+
+// Box filter with fractional coverage; colors are averaged premultiplied by
+// alpha so that transparent pixels do not bleed their hidden colors, either
+// gamma-encoded or in `linear` light
+fn area_average(image: &RgbaImage, width: u32, height: u32, linear_light: bool) -> RgbaImage {
+    let decode = |c: u8| if linear_light { linear(c) } else { c as f32 };
+    let columns = spans(image.width(), width);
+    let rows = spans(image.height(), height);
+    RgbaImage::from_fn(width, height, |x, y| {
+        let mut sum = [0.0f32; 4];
+        let mut area = 0.0;
+        for &(sy, wy) in &rows[y as usize] {
+            for &(sx, wx) in &columns[x as usize] {
+                let weight = wx * wy;
+                let [r, g, b, a] = image.get_pixel(sx, sy).0;
+                let opacity = a as f32 * weight;
+                sum[0] += decode(r) * opacity;
+                sum[1] += decode(g) * opacity;
+                sum[2] += decode(b) * opacity;
+                sum[3] += opacity;
+                area += weight;
+            }
+        }
+        if sum[3] <= 0.0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+        let channel = |c: f32| if linear_light { encode(c / sum[3]) } else { (c / sum[3]).round().clamp(0.0, 255.0) as u8 };
+        Rgba([channel(sum[0]), channel(sum[1]), channel(sum[2]), (sum[3] / area).round().clamp(0.0, 255.0) as u8])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_area_average() {
+        // 2x2 checkerboard blocks of black and white, with one transparent red pixel
+        let mut image = RgbaImage::from_fn(4, 2, |x, y| if (x + y) % 2 == 0 { Rgba([0, 0, 0, 255]) } else { Rgba([255, 255, 255, 255]) });
+        image.put_pixel(3, 1, Rgba([255, 0, 0, 0]));
+        let small = area_average(&image, 2, 1, false);
+        assert_eq!(small.get_pixel(0, 0), &Rgba([128, 128, 128, 255]));
+        assert_eq!(small.get_pixel(1, 0), &Rgba([170, 170, 170, 191]));
+
+        // Fractional coverage: three pixels into two
+        let image = RgbaImage::from_fn(3, 1, |x, _| Rgba([[0, 90, 180][x as usize], 0, 0, 255]));
+        let small = area_average(&image, 2, 1, false);
+        assert_eq!((small.get_pixel(0, 0)[0], small.get_pixel(1, 0)[0]), (30, 150));
+
+        assert_eq!("bicubic".parse(), Ok(ResizeFilter::CatmullRom));
+    }
+
+    #[test]
+    fn test_area_average_keeps_mean_luminance() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(2, 2, |x, _| if x == 0 { Rgba([0, 0, 0, 255]) } else { Rgba([255, 255, 255, 255]) }));
+        let grey = |luma: Luma| luma.grey(ResizeFilter::Area.resize(&image, 1, 1, luma).to_rgba8().get_pixel(0, 0).to_owned());
+        // Half the luminance of white, whose lightness L* is 76
+        assert_eq!(grey(Luma::Linear), 128);
+        assert_eq!(grey(Luma::Lightness), 194);
+        // Averaging encoded values would give mid-grey, a fifth of the luminance
+        assert_eq!(Luma::Linear.grey(Rgba([128, 128, 128, 255])), 55);
+    }
+}
@@ -0,0 +1,115 @@
+use std::str::FromStr;
+
+use crate::fonts::{CellBitmap, CellMetrics, FontChain};
+use crate::Charset;
+
+/// Number of samples per cell compared against the glyph shapes, horizontally and vertically.
+pub(crate) const SHAPE_COLUMNS: u32 = 4;
+pub(crate) const SHAPE_ROWS: u32 = 8;
+
+/// How closely a cell matches a glyph in shape mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShapeMetric {
+    /// Smallest sum of squared differences.
+    #[default]
+    Ssd,
+    /// Highest structural similarity index.
+    Ssim,
+}
+
+impl FromStr for ShapeMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ssd" => Ok(ShapeMetric::Ssd),
+            "ssim" => Ok(ShapeMetric::Ssim),
+            _ => Err(format!("unknown shape metric '{}' (expected ssd or ssim)", s)),
+        }
+    }
+}
+
+/// Downsampled bitmaps of the glyphs of a charset, computed once and matched
+/// against every cell.
+pub(crate) struct GlyphShapes {
+    glyphs: Vec<(String, Vec<f32>)>,
+}
+
+impl GlyphShapes {
+    pub fn new(fonts: &FontChain, charset: &Charset, metrics: &CellMetrics) -> GlyphShapes {
+        let mut glyphs: Vec<(String, Vec<f32>)> = charset
+            .glyphs()
+            .filter_map(|glyph| {
+                let ch = glyph.chars().next()?;
+                let bitmap = CellBitmap::new(fonts.font_for(ch), ch, metrics);
+                Some((glyph.to_string(), bitmap.downsample(SHAPE_COLUMNS, SHAPE_ROWS)))
+            })
+            .collect();
+
+        // Glyphs never fill their whole cell; stretch coverage so the most
+        // inked sample of the charset stands for full brightness
+        let max = glyphs.iter().flat_map(|(_, shape)| shape.iter().copied()).fold(0.0, f32::max);
+        if max > 0.0 {
+            for (_, shape) in &mut glyphs {
+                shape.iter_mut().for_each(|value| *value /= max);
+            }
+        }
+
+        GlyphShapes { glyphs }
+    }
+
+    /// The glyph whose shape is closest to `cell`, sampled on the shape grid
+    /// with brightness from 0 to 1.
+    pub fn best_match(&self, cell: &[f32], metric: ShapeMetric) -> &str {
+        let score = |shape: &[f32]| match metric {
+            ShapeMetric::Ssd => -cell.iter().zip(shape).map(|(a, b)| (a - b) * (a - b)).sum::<f32>(),
+            ShapeMetric::Ssim => ssim(cell, shape),
+        };
+        self.glyphs
+            .iter()
+            .map(|(glyph, shape)| (glyph.as_str(), score(shape)))
+            .fold((" ", f32::NEG_INFINITY), |best, candidate| if candidate.1 > best.1 { candidate } else { best })
+            .0
+    }
+}
+
+// Structural similarity of two sample vectors with values from 0 to 1
+fn ssim(a: &[f32], b: &[f32]) -> f32 {
+    const C1: f32 = 0.01 * 0.01;
+    const C2: f32 = 0.03 * 0.03;
+
+    let n = a.len() as f32;
+    let mean_a = a.iter().sum::<f32>() / n;
+    let mean_b = b.iter().sum::<f32>() / n;
+    let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        var_a += (x - mean_a) * (x - mean_a);
+        var_b += (y - mean_b) * (y - mean_b);
+        covariance += (x - mean_a) * (y - mean_b);
+    }
+    let (var_a, var_b, covariance) = (var_a / n, var_b / n, covariance / n);
+
+    ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+        / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FontSource;
+
+    #[test]
+    fn test_best_match_follows_shape() {
+        let fonts = FontChain::new(&FontSource::default(), &[]).unwrap();
+        let metrics = CellMetrics::new(fonts.primary(), 24.0);
+        let shapes = GlyphShapes::new(&fonts, &Charset::from_glyphs(" |-#"), &metrics);
+
+        // A bright vertical bar down the middle of the cell
+        let cell: Vec<f32> = (0..SHAPE_COLUMNS * SHAPE_ROWS)
+            .map(|i| if matches!(i % SHAPE_COLUMNS, 1 | 2) { 1.0 } else { 0.0 })
+            .collect();
+        assert_eq!(shapes.best_match(&cell, ShapeMetric::Ssd), "|");
+        assert_eq!(shapes.best_match(&cell, ShapeMetric::Ssim), "|");
+        assert_eq!(shapes.best_match(&[0.0; 32], ShapeMetric::Ssd), " ");
+    }
+}
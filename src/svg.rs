@@ -0,0 +1,116 @@
+use crate::color::to_hex;
+use crate::html::{css_font_family, escape};
+use crate::fonts::FontChain;
+use crate::{AsciiArt, Options, Result};
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+pub(crate) fn render(art: &AsciiArt, options: &Options, fonts: &FontChain) -> Result<String> {
+    let metrics = options.cell_metrics(fonts);
+    let cell_width = metrics.width * art.cell_columns() as f32;
+    let width = (art.width() as f32 * cell_width).ceil();
+    let height = art.height() as f32 * metrics.height;
+
+    let mut out = String::new();
+    out.push_str(&format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+        width, height, width, height
+    ));
+
+    out.push_str("<style>\n");
+    let font_family = if options.embed_font {
+        out.push_str(&format!(
+            "@font-face {{ font-family: \"crascii\"; src: url(data:font/ttf;base64,{}); }}\n",
+            base64(&options.font_source().data()?)
+        ));
+        "\"crascii\", monospace".to_string()
+    } else {
+        css_font_family(&options.font_family)
+    };
+    out.push_str(&format!("text {{ font-family: {}; font-size: {}px; }}\n", font_family, metrics.scale.y));
+    out.push_str("</style>\n");
+
+    if let Some(background) = options.paper() {
+        out.push_str(&format!("<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>\n", to_hex(background)));
+    }
+
+    // One text element per run of identically colored characters on a row,
+    // stretched to the exact cell grid so any fallback font keeps the layout
+    for (y, row) in art.rows().enumerate() {
+        let top = y as f32 * metrics.height;
+        let baseline = top + metrics.ascent;
+
+        let mut x = 0;
+        while x < row.len() {
+            let background = row[x].background();
+            let run = row[x..].iter().take_while(|cell| cell.background() == background).count();
+            if let Some(background) = background {
+                out.push_str(&format!(
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>\n",
+                    x as f32 * cell_width,
+                    top,
+                    run as f32 * cell_width,
+                    metrics.height,
+                    to_hex(background)
+                ));
+            }
+            x += run;
+        }
+
+        let mut x = 0;
+        while x < row.len() {
+            let color = to_hex(row[x].color());
+            let run = row[x..].iter().take_while(|cell| to_hex(cell.color()) == color).count();
+            let cells = &row[x..x + run];
+
+            if cells.iter().any(|cell| !cell.ch().is_whitespace()) {
+                out.push_str(&format!(
+                    "<text x=\"{}\" y=\"{}\" textLength=\"{}\" lengthAdjust=\"spacing\" fill=\"{}\" xml:space=\"preserve\">",
+                    x as f32 * cell_width,
+                    baseline,
+                    run as f32 * cell_width,
+                    color
+                ));
+                for cell in cells {
+                    escape(cell.glyph(), &mut out);
+                    out.push_str(&art.padding(cell));
+                }
+                out.push_str("</text>\n");
+            }
+            x += run;
+        }
+    }
+
+    out.push_str("</svg>\n");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+}
@@ -0,0 +1,275 @@
+use image::{GrayImage, Rgba};
+use imageproc::contrast::stretch_contrast_mut;
+use std::str::FromStr;
+
+use crate::color::{lab, linear};
+
+/// How a pixel color is reduced to a grey level.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Luma {
+    /// Rec. 601 weights on the gamma-encoded channels.
+    Rec601,
+    /// Rec. 709 weights on the gamma-encoded channels.
+    #[default]
+    Rec709,
+    /// Relative luminance Y of the linear-light channels.
+    Linear,
+    /// CIE lightness L*, perceptually uniform.
+    Lightness,
+}
+
+impl Luma {
+    /// Grey level of `pixel`, ignoring its alpha.
+    pub fn grey(self, pixel: Rgba<u8>) -> u8 {
+        let [r, g, b, _] = pixel.0;
+        match self {
+            Luma::Rec601 => (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32) as u8,
+            // https://en.wikipedia.org/wiki/Grayscale
+            Luma::Rec709 => (0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32) as u8,
+            Luma::Linear => {
+                let y = 0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b);
+                (y * 255.0).round().clamp(0.0, 255.0) as u8
+            },
+            Luma::Lightness => (lab([r, g, b])[0] * 2.55).round().clamp(0.0, 255.0) as u8,
+        }
+    }
+}
+
+impl FromStr for Luma {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rec601" | "601" => Ok(Luma::Rec601),
+            "rec709" | "709" => Ok(Luma::Rec709),
+            "linear" => Ok(Luma::Linear),
+            "lightness" | "l*" => Ok(Luma::Lightness),
+            _ => Err(format!("unknown luma '{}' (expected rec601, rec709, linear or lightness)", s)),
+        }
+    }
+}
+
+/// Automatic spreading of the grey levels over the full range.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Levels {
+    #[default]
+    None,
+    /// Stretches the darkest level to black and the brightest to white.
+    Auto,
+    /// Global histogram equalization.
+    Equalize,
+    /// Contrast limited adaptive histogram equalization over a grid of
+    /// `tiles` x `tiles` regions, each histogram bin clipped at `clip_limit`
+    /// times the mean bin count.
+    Clahe { tiles: u32, clip_limit: f32 },
+}
+
+impl Levels {
+    /// `Clahe` over an 8x8 grid, clipped at twice the mean bin count.
+    pub const CLAHE: Levels = Levels::Clahe { tiles: 8, clip_limit: 2.0 };
+}
+
+impl FromStr for Levels {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Levels::None),
+            "auto" | "auto-levels" => Ok(Levels::Auto),
+            "equalize" => Ok(Levels::Equalize),
+            "clahe" => Ok(Levels::CLAHE),
+            _ => Err(format!("unknown levels '{}' (expected none, auto, equalize or clahe)", s)),
+        }
+    }
+}
+
+/// Adjustments of the grey levels that drive glyph selection, applied in
+/// order: levels, gamma, contrast, brightness and invert. Cell colors are
+/// left as they are.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tone {
+    /// Offset added to every level, from -1 (all black) to 1 (all white).
+    pub brightness: f32,
+    /// Factor scaling the distance of every level from mid-grey.
+    pub contrast: f32,
+    /// Exponent brightening the mid-tones above 1 and darkening them below.
+    pub gamma: f32,
+    /// Swaps dark and bright levels.
+    pub invert: bool,
+    pub levels: Levels,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone { brightness: 0.0, contrast: 1.0, gamma: 1.0, invert: false, levels: Levels::None }
+    }
+}
+
+impl Tone {
+    // Pixels for which `is_blank` holds are adjusted too but left out of the
+    // statistics of the levels
+    pub(crate) fn apply(&self, image: &mut GrayImage, is_blank: impl Fn(u32, u32) -> bool) {
+        match self.levels {
+            Levels::None => {},
+            Levels::Auto => {
+                let (min, max) = image
+                    .enumerate_pixels()
+                    .filter(|&(x, y, _)| !is_blank(x, y))
+                    .fold((255, 0), |(min, max), (_, _, p)| (p[0].min(min), p[0].max(max)));
+                if min < max {
+                    stretch_contrast_mut(image, min, max, 0, 255);
+                }
+            },
+            Levels::Equalize => equalize(image, &is_blank),
+            Levels::Clahe { tiles, clip_limit } => clahe(image, tiles, clip_limit, &is_blank),
+        }
+
+        if *self == (Tone { levels: self.levels, ..Tone::default() }) {
+            return;
+        }
+        let lut: Vec<u8> = (0..=255u8)
+            .map(|level| {
+                let mut value = (level as f32 / 255.0).powf(1.0 / self.gamma.max(f32::EPSILON));
+                value = (value - 0.5) * self.contrast + 0.5 + self.brightness;
+                let value = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+                if self.invert { 255 - value } else { value }
+            })
+            .collect();
+        for pixel in image.pixels_mut() {
+            pixel[0] = lut[pixel[0] as usize];
+        }
+    }
+}
+
+// Maps every level through the cumulative histogram of the non-blank pixels
+fn equalize(image: &mut GrayImage, is_blank: impl Fn(u32, u32) -> bool) {
+    let mut histogram = [0u32; 256];
+    for (_, _, pixel) in image.enumerate_pixels().filter(|&(x, y, _)| !is_blank(x, y)) {
+        histogram[pixel[0] as usize] += 1;
+    }
+    let count: u32 = histogram.iter().sum();
+    if count == 0 {
+        return;
+    }
+    let mut cdf = 0;
+    let lut = histogram.map(|n| {
+        cdf += n;
+        (cdf as f32 / count as f32 * 255.0).round() as u8
+    });
+    for pixel in image.pixels_mut() {
+        pixel[0] = lut[pixel[0] as usize];
+    }
+}
+
+// Equalizes each tile of a `tiles` x `tiles` grid with a clipped histogram
+// of its non-blank pixels, blending the mappings of the four nearest tile
+// centers for every pixel
+fn clahe(image: &mut GrayImage, tiles: u32, clip_limit: f32, is_blank: impl Fn(u32, u32) -> bool) {
+    let (width, height) = image.dimensions();
+    let (tiles_x, tiles_y) = (tiles.clamp(1, width.max(1)), tiles.clamp(1, height.max(1)));
+    let bounds = |tile: u32, tiles: u32, size: u32| (tile * size / tiles, (tile + 1) * size / tiles);
+
+    let mut luts = Vec::with_capacity((tiles_x * tiles_y) as usize);
+    for ty in 0..tiles_y {
+        for tx in 0..tiles_x {
+            let ((x0, x1), (y0, y1)) = (bounds(tx, tiles_x, width), bounds(ty, tiles_y, height));
+            let mut histogram = [0f32; 256];
+            let mut count = 0.0;
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    if !is_blank(x, y) {
+                        histogram[image.get_pixel(x, y)[0] as usize] += 1.0;
+                        count += 1.0;
+                    }
+                }
+            }
+            let count = f32::max(count, 1.0);
+            let limit = (clip_limit * count / 256.0).max(1.0);
+            let excess: f32 = histogram.iter().map(|&n| (n - limit).max(0.0)).sum();
+            let mut cdf = 0.0;
+            let lut: Vec<f32> = histogram
+                .iter()
+                .map(|&n| {
+                    cdf += n.min(limit) + excess / 256.0;
+                    cdf / count * 255.0
+                })
+                .collect();
+            luts.push(lut);
+        }
+    }
+
+    // Index of the tile center at or before `position`, the next one, and
+    // the weight of the next one
+    let neighbours = |position: u32, tiles: u32, size: u32| {
+        let t = ((position as f32 + 0.5) * tiles as f32 / size as f32 - 0.5).max(0.0);
+        let first = (t as u32).min(tiles - 1);
+        (first, (first + 1).min(tiles - 1), (t - first as f32).clamp(0.0, 1.0))
+    };
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let (x0, x1, wx) = neighbours(x, tiles_x, width);
+        let (y0, y1, wy) = neighbours(y, tiles_y, height);
+        let level = pixel[0] as usize;
+        let at = |tx: u32, ty: u32| luts[(ty * tiles_x + tx) as usize][level];
+        let top = at(x0, y0) * (1.0 - wx) + at(x1, y0) * wx;
+        let bottom = at(x0, y1) * (1.0 - wx) + at(x1, y1) * wx;
+        pixel[0] = (top * (1.0 - wy) + bottom * wy).round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_luma_formulas() {
+        let green = Rgba([0, 128, 0, 255]);
+        assert_eq!(Luma::Rec709.grey(green), 91);
+        assert_eq!(Luma::Rec601.grey(green), 75);
+        assert_eq!(Luma::Linear.grey(green), 39);
+        assert_eq!(Luma::Lightness.grey(green), 118);
+        assert_eq!("l*".parse(), Ok(Luma::Lightness));
+    }
+
+    #[test]
+    fn test_adjustments() {
+        let ramp = || GrayImage::from_fn(4, 1, |x, _| image::Luma([100 + x as u8 * 20]));
+        let levels = |tone: Tone| {
+            let mut image = ramp();
+            tone.apply(&mut image, |_, _| false);
+            image.into_raw()
+        };
+        assert_eq!(levels(Tone::default()), [100, 120, 140, 160]);
+        assert_eq!(levels(Tone { levels: Levels::Auto, ..Tone::default() }), [0, 85, 170, 255]);
+        assert_eq!(levels(Tone { levels: Levels::Auto, invert: true, ..Tone::default() }), [255, 170, 85, 0]);
+        assert_eq!(levels(Tone { brightness: 0.5, ..Tone::default() }), [228, 248, 255, 255]);
+        assert_eq!(levels(Tone { contrast: 0.0, ..Tone::default() }), [128; 4]);
+        assert!(levels(Tone { gamma: 2.0, ..Tone::default() })[0] > 100);
+    }
+
+    #[test]
+    fn test_levels_ignore_blank_pixels() {
+        // The ramp of `test_adjustments` next to two black blank pixels
+        let mut image = GrayImage::from_fn(6, 1, |x, _| image::Luma([if x < 2 { 0 } else { 100 + (x - 2) as u8 * 20 }]));
+        Tone { levels: Levels::Auto, ..Tone::default() }.apply(&mut image, |x, _| x < 2);
+        assert_eq!(&image.as_raw()[2..], [0, 85, 170, 255]);
+
+        let mut image = GrayImage::from_fn(6, 1, |x, _| image::Luma([if x < 2 { 0 } else { 100 + (x - 2) as u8 * 20 }]));
+        Tone { levels: Levels::Equalize, ..Tone::default() }.apply(&mut image, |x, _| x < 2);
+        assert_eq!(&image.as_raw()[2..], [64, 128, 191, 255]);
+    }
+
+    #[test]
+    fn test_clahe_spreads_each_region() {
+        // Dim left half, bright right half, each with a little texture
+        let image = GrayImage::from_fn(32, 16, |x, y| image::Luma([if x < 16 { 20 } else { 220 } + ((x + y) % 4) as u8 * 5]));
+        let span = |clip_limit: f32, x0: u32| {
+            let mut image = image.clone();
+            clahe(&mut image, 2, clip_limit, |_, _| false);
+            let levels: Vec<u8> = (0..16).flat_map(|y| (x0..x0 + 8).map(move |x| (x, y))).map(|(x, y)| image.get_pixel(x, y)[0]).collect();
+            levels.iter().max().unwrap() - levels.iter().min().unwrap()
+        };
+        assert!(span(256.0, 0) > 150 && span(256.0, 24) > 150, "{} {}", span(256.0, 0), span(256.0, 24));
+        // Clipping limits the contrast gain
+        assert!(span(4.0, 0) < span(256.0, 0));
+    }
+}